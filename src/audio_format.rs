@@ -0,0 +1,157 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::codecs::{
+    CodecType, Decoder, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC,
+    CODEC_TYPE_MP1, CODEC_TYPE_MP2, CODEC_TYPE_MP3, CODEC_TYPE_NULL, CODEC_TYPE_OPUS,
+    CODEC_TYPE_VORBIS,
+};
+use symphonia::core::formats::{FormatOptions, Track};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::{Hint, ProbeResult};
+use symphonia::default::{get_codecs, get_probe};
+
+// files that are never worth probing (covers, playlists, notes...)
+const SKIPPED_EXTENSIONS: [&str; 12] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "webp", "txt", "nfo", "log", "cue", "m3u", "pdf",
+];
+
+/// Audio codec families that can be enabled in the settings.
+/// A file is playable when its codec belongs to an enabled family
+/// and symphonia was built with a decoder for it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum AudioFormat {
+    Mp3, // MPEG audio layers I, II and III
+    Flac,
+    Vorbis,
+    Opus,
+    Aac,
+    Alac,
+    Pcm, // WAV, AIFF, ...
+    Adpcm,
+}
+
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 8] = [
+        AudioFormat::Mp3,
+        AudioFormat::Flac,
+        AudioFormat::Vorbis,
+        AudioFormat::Opus,
+        AudioFormat::Aac,
+        AudioFormat::Alac,
+        AudioFormat::Pcm,
+        AudioFormat::Adpcm,
+    ];
+
+    pub fn is_lossless(self) -> bool {
+        matches!(
+            self,
+            AudioFormat::Flac | AudioFormat::Alac | AudioFormat::Pcm
+        )
+    }
+
+    fn from_codec(codec: CodecType) -> Option<Self> {
+        match codec {
+            CODEC_TYPE_NULL => None,
+            CODEC_TYPE_MP1 | CODEC_TYPE_MP2 | CODEC_TYPE_MP3 => Some(AudioFormat::Mp3),
+            CODEC_TYPE_FLAC => Some(AudioFormat::Flac),
+            CODEC_TYPE_VORBIS => Some(AudioFormat::Vorbis),
+            CODEC_TYPE_OPUS => Some(AudioFormat::Opus),
+            CODEC_TYPE_AAC => Some(AudioFormat::Aac),
+            CODEC_TYPE_ALAC => Some(AudioFormat::Alac),
+            _ => {
+                // PCM and ADPCM have dozens of variants, use the registered short name
+                let name = get_codecs().get_codec(codec)?.short_name;
+                if name.starts_with("pcm") {
+                    Some(AudioFormat::Pcm)
+                } else if name.starts_with("adpcm") {
+                    Some(AudioFormat::Adpcm)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Lowercase extension of the path, used as a probe hint.
+pub fn extension_hint(path: &Path) -> Option<String> {
+    let ext = path.extension()?.to_str()?;
+    Some(ext.to_lowercase())
+}
+
+/// Probes the content of the file. The extension is only used as a hint.
+pub fn probe(path: &Path) -> Option<ProbeResult> {
+    probe_file(File::open(path).ok()?, path)
+}
+
+/// Probes the content of the already opened file at `path`.
+pub fn probe_file(file: File, path: &Path) -> Option<ProbeResult> {
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = extension_hint(path) {
+        hint.with_extension(&ext);
+    }
+
+    get_probe()
+        .format(
+            &hint,
+            mss,
+            // trims the encoder delay and padding the container tells (e.g. LAME header)
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .ok()
+}
+
+/// Whether the file is never worth probing.
+pub fn is_skipped(path: &Path) -> bool {
+    match extension_hint(path) {
+        None => false,
+        Some(ext) => SKIPPED_EXTENSIONS.contains(&ext.as_str()),
+    }
+}
+
+/// Returns the format of the probed file if symphonia has a decoder for it.
+pub fn get_format(probed: &ProbeResult) -> Option<AudioFormat> {
+    let track = get_audio_track(probed)?;
+    let format = AudioFormat::from_codec(track.codec_params.codec)?;
+
+    // only accept codecs symphonia has a decoder for
+    get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+
+    Some(format)
+}
+
+/// Decoder for the audio track of the probed file, with the id of the track.
+pub fn make_decoder(probed: &ProbeResult) -> Option<(u32, Box<dyn Decoder>)> {
+    let track = get_audio_track(probed)?;
+    let decoder = get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+    Some((track.id, decoder))
+}
+
+/// Duration declared by the container, if any.
+pub fn get_duration(probed: &ProbeResult) -> Option<Duration> {
+    let params = &get_audio_track(probed)?.codec_params;
+    let time = params.time_base?.calc_time(params.n_frames?);
+    Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+}
+
+fn get_audio_track(probed: &ProbeResult) -> Option<&Track> {
+    probed
+        .format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+}
@@ -1,8 +1,27 @@
+mod duplicates;
+mod duplicates_loop;
+mod duplicates_messages;
+mod library_index;
 mod loader_loop;
 mod loader_messages;
 mod main_loop;
 mod music_dir;
 mod player_loop;
 mod player_messages;
+mod replay;
+mod scan_rules;
+mod scanner_loop;
+mod scanner_messages;
+mod sequential;
+mod session;
+mod shuffle_bag;
+mod shuffle_history;
+mod spacing;
+mod track_chain;
+mod track_source;
+mod track_stats;
+mod watcher_loop;
+mod watcher_messages;
+mod weighted_sampler;
 
 pub use main_loop::run;
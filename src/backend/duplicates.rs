@@ -0,0 +1,438 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::audio_format;
+use crate::backend::library_index::IndexEntry;
+use crate::duplicate_report::{DuplicateGroup, DuplicateKind, DuplicateReport};
+
+const FRAMES_PER_SEC: usize = 10;
+const MAX_FRAMES: usize = 60 * FRAMES_PER_SEC; // only the first minute is fingerprinted
+const MIN_FRAMES: usize = 10 * FRAMES_PER_SEC; // shorter fingerprints match too easily
+const SILENCE_RMS: f32 = 0.01;
+const MAX_BIT_ERROR_RATE: f32 = 0.2;
+const MAX_DURATION_GAP: Duration = Duration::from_secs(2);
+
+/// Shape of the loudness over the start of a track, after the leading silence:
+/// one bit per frame, set when the frame is louder than the previous one.
+/// Re-encodes of a recording keep that shape, other recordings don't.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Fingerprint {
+    frames: usize, // 0 when the file could not be decoded or is too short
+    bits: Vec<u32>,
+}
+
+impl Fingerprint {
+    pub fn compute(path: &Path) -> Self {
+        let envelope = get_loudness_envelope(path).unwrap_or_default();
+        if envelope.len() <= MIN_FRAMES {
+            return Self::default();
+        }
+        let frames = envelope.len() - 1;
+        let mut bits = vec![0; frames.div_ceil(32)];
+        for (i, pair) in envelope.windows(2).enumerate() {
+            if pair[1] > pair[0] {
+                bits[i / 32] |= 1 << (i % 32);
+            }
+        }
+        Self { frames, bits }
+    }
+
+    fn matches(&self, other: &Fingerprint) -> bool {
+        // only whole words are compared
+        let words = self.frames.min(other.frames) / 32;
+        if words * 32 < MIN_FRAMES {
+            return false;
+        }
+        let max_errors = (words * 32) as f32 * MAX_BIT_ERROR_RATE;
+        let mut errors = 0;
+        for (a, b) in self.bits.iter().zip(&other.bits).take(words) {
+            errors += (a ^ b).count_ones();
+            if errors as f32 > max_errors {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// RMS of the mono mix of each frame
+fn get_loudness_envelope(path: &Path) -> Option<Vec<f32>> {
+    let mut probed = audio_format::probe(path)?;
+    let (track_id, mut decoder) = audio_format::make_decoder(&probed)?;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut envelope = vec![];
+    let (mut sum, mut count) = (0.0, 0);
+
+    while envelope.len() < MAX_FRAMES {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(_) => break, // end of stream
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue, // corrupted packet
+            Err(_) => break,
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let frame_len = spec.rate as usize / FRAMES_PER_SEC;
+        if sample_buf
+            .as_ref()
+            .is_none_or(|buf| buf.capacity() < decoded.capacity() * channels)
+        {
+            sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buf = sample_buf.as_mut().unwrap();
+        buf.copy_interleaved_ref(decoded);
+
+        for samples in buf.samples().chunks(channels) {
+            let mono = samples.iter().sum::<f32>() / channels as f32;
+            sum += mono * mono;
+            count += 1;
+            if count >= frame_len {
+                let rms = (sum / count as f32).sqrt();
+                // re-encodes may pad the start with more or less silence
+                if !envelope.is_empty() || rms > SILENCE_RMS {
+                    envelope.push(rms);
+                }
+                (sum, count) = (0.0, 0);
+            }
+        }
+    }
+    Some(envelope)
+}
+
+pub fn hash_content(path: &Path) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => return Some(hasher.digest()),
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Groups the entries that are copies of each other. Entries need their hash and
+/// fingerprint to be compared, the hash is only needed when another file has the same size.
+pub fn find_groups(entries: &[IndexEntry]) -> DuplicateReport {
+    let mut sets = DisjointSets::new(entries.len());
+
+    // exact copies
+    let mut by_content: HashMap<(u64, u64), usize> = HashMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        if let Some(hash) = e.content_hash {
+            let first = *by_content.entry((e.size, hash)).or_insert(i);
+            sets.union(first, i);
+        }
+    }
+
+    // same recording: only tracks of about the same duration are compared
+    let mut by_duration: Vec<(Duration, usize)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.fingerprint.as_ref().is_some_and(|f| f.frames > 0))
+        .filter_map(|(i, e)| Some((e.duration?, i)))
+        .collect();
+    by_duration.sort();
+    for (n, &(duration, i)) in by_duration.iter().enumerate() {
+        for &(other_duration, j) in &by_duration[n + 1..] {
+            if other_duration - duration > MAX_DURATION_GAP {
+                break;
+            }
+            let (a, b) = (sets.find(i), sets.find(j));
+            if a == b {
+                continue;
+            }
+            // compared with the first track of each group: a near copy of a near copy
+            // can be too far from the original to be the same recording
+            let (Some(fa), Some(fb)) = (&entries[a].fingerprint, &entries[b].fingerprint) else {
+                continue;
+            };
+            if fa.matches(fb) {
+                sets.union(a, b);
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<&IndexEntry>> = HashMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        members.entry(sets.find(i)).or_default().push(e);
+    }
+    let mut groups: Vec<DuplicateGroup> = members
+        .into_values()
+        .filter(|m| m.len() > 1)
+        .map(|mut m| {
+            m.sort_by_key(|e| get_preference(e));
+            let first = (m[0].size, m[0].content_hash);
+            let exact = m
+                .iter()
+                .all(|e| e.content_hash.is_some() && (e.size, e.content_hash) == first);
+            DuplicateGroup {
+                kind: if exact {
+                    DuplicateKind::Exact
+                } else {
+                    DuplicateKind::Recording
+                },
+                paths: m.into_iter().map(|e| e.path.clone()).collect(),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    DuplicateReport { groups }
+}
+
+// lossless first, then the highest bitrate, then the shortest path
+fn get_preference(e: &IndexEntry) -> (bool, Reverse<u64>, usize) {
+    let lossless = e.format.is_some_and(|f| f.is_lossless());
+    let secs = e.duration.map_or(0, |d| d.as_secs()).max(1);
+    (!lossless, Reverse(e.size / secs), e.path.as_os_str().len())
+}
+
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(n: usize) -> Self {
+        Self {
+            parents: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[b] = a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::audio_format::AudioFormat;
+    use crate::backend::track_source::tests::write_wav;
+
+    const RATE: u32 = 8000;
+    const FRAME_LEN: usize = RATE as usize / FRAMES_PER_SEC;
+
+    // noise with a random loudness for each frame
+    fn make_recording(seed: u64, frames: usize) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut samples = vec![];
+        for _ in 0..frames {
+            let loudness = rng.gen_range(0.1..0.8);
+            samples.extend((0..FRAME_LEN).map(|_| loudness * rng.gen_range(-1.0..1.0f32)));
+        }
+        samples
+    }
+
+    fn fingerprint(name: &str, samples: &[f32]) -> Fingerprint {
+        Fingerprint::compute(&write_wav(name, 1, RATE, samples))
+    }
+
+    // `frames` bits, those at `set` being 1
+    fn make_fingerprint(frames: usize, set: impl IntoIterator<Item = usize>) -> Fingerprint {
+        let mut bits = vec![0; frames.div_ceil(32)];
+        for i in set {
+            bits[i / 32] |= 1 << (i % 32);
+        }
+        Fingerprint { frames, bits }
+    }
+
+    fn make_entry(path: &str, size: u64, hash: Option<u64>, f: Option<Fingerprint>) -> IndexEntry {
+        IndexEntry {
+            path: PathBuf::from(path),
+            modified: SystemTime::UNIX_EPOCH,
+            size,
+            format: Some(match path.rsplit('.').next() {
+                Some("flac") => AudioFormat::Flac,
+                _ => AudioFormat::Mp3,
+            }),
+            name: None,
+            artist: None,
+            album: None,
+            track_number: None,
+            disc_number: None,
+            duration: Some(Duration::from_secs(200)),
+            content_hash: hash,
+            fingerprint: f,
+        }
+    }
+
+    fn get_paths(report: &DuplicateReport) -> Vec<(DuplicateKind, Vec<&str>)> {
+        report
+            .groups
+            .iter()
+            .map(|g| {
+                (
+                    g.kind,
+                    g.paths.iter().map(|p| p.to_str().unwrap()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_quieter_copy_with_more_silence_has_the_same_fingerprint() {
+        let original = make_recording(1, 200);
+        let mut copy = vec![0.0; 5 * FRAME_LEN];
+        copy.extend(original.iter().map(|s| s * 0.5));
+        let a = fingerprint("fingerprint-original", &original);
+        let b = fingerprint("fingerprint-copy", &copy);
+        assert_eq!(a.frames, 199);
+        assert_eq!((a.frames, &a.bits), (b.frames, &b.bits));
+        assert!(a.matches(&b));
+    }
+
+    #[test]
+    fn other_recordings_do_not_match() {
+        let a = fingerprint("fingerprint-first", &make_recording(1, 200));
+        let b = fingerprint("fingerprint-second", &make_recording(2, 200));
+        assert!(!a.matches(&b));
+    }
+
+    #[test]
+    fn short_or_silent_tracks_have_no_fingerprint() {
+        let short = fingerprint("fingerprint-short", &make_recording(1, MIN_FRAMES));
+        let silent = fingerprint("fingerprint-silent", &vec![0.0; 200 * FRAME_LEN]);
+        for f in [&short, &silent] {
+            assert_eq!(f.frames, 0);
+            assert!(!f.matches(f));
+        }
+    }
+
+    #[test]
+    fn fingerprints_match_up_to_the_bit_error_rate() {
+        // 4 whole words: up to 25.6 different bits
+        let original = make_fingerprint(128, []);
+        assert!(original.matches(&make_fingerprint(128, 0..25)));
+        assert!(!original.matches(&make_fingerprint(128, 0..26)));
+        // the bits past the last whole word of the shorter one are left out
+        assert!(original.matches(&make_fingerprint(140, (0..25).chain(128..140))));
+        // too few whole words to compare
+        assert!(!make_fingerprint(90, []).matches(&make_fingerprint(90, [])));
+    }
+
+    #[test]
+    fn exact_copies_and_re_encodes_are_grouped() {
+        let f = make_fingerprint(128, []);
+        let entries = [
+            make_entry("/music/a.mp3", 100, Some(1), Some(f.clone())),
+            make_entry("/music/copy/a.mp3", 100, Some(1), Some(f.clone())),
+            make_entry(
+                "/music/b.mp3",
+                120,
+                Some(2),
+                Some(make_fingerprint(128, 0..64)),
+            ),
+            make_entry(
+                "/music/b.ogg",
+                90,
+                Some(3),
+                Some(make_fingerprint(128, 0..60)),
+            ),
+            // same content hash, another size
+            make_entry("/music/c.mp3", 130, Some(1), None),
+        ];
+        assert_eq!(
+            get_paths(&find_groups(&entries)),
+            vec![
+                (
+                    DuplicateKind::Exact,
+                    vec!["/music/a.mp3", "/music/copy/a.mp3"]
+                ),
+                (
+                    DuplicateKind::Recording,
+                    vec!["/music/b.mp3", "/music/b.ogg"]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_of_other_durations_are_not_compared() {
+        let f = make_fingerprint(128, []);
+        let mut longer = make_entry("/music/b.mp3", 100, None, Some(f.clone()));
+        longer.duration = Some(Duration::from_secs(203));
+        let entries = [make_entry("/music/a.mp3", 100, None, Some(f)), longer];
+        assert!(find_groups(&entries).groups.is_empty());
+    }
+
+    #[test]
+    fn near_copies_of_near_copies_are_not_grouped() {
+        // b is 20 bits away from a and c, c is 40 bits away from a
+        let entries = [
+            make_entry("/music/a.mp3", 100, None, Some(make_fingerprint(128, []))),
+            make_entry(
+                "/music/b.mp3",
+                100,
+                None,
+                Some(make_fingerprint(128, 0..20)),
+            ),
+            make_entry(
+                "/music/c.mp3",
+                100,
+                None,
+                Some(make_fingerprint(128, 0..40)),
+            ),
+        ];
+        assert_eq!(
+            get_paths(&find_groups(&entries)),
+            vec![(
+                DuplicateKind::Recording,
+                vec!["/music/a.mp3", "/music/b.mp3"]
+            )]
+        );
+    }
+
+    #[test]
+    fn the_lossless_then_largest_then_shortest_path_copy_comes_first() {
+        let f = || Some(make_fingerprint(128, []));
+        let entries = [
+            make_entry("/music/mp3/high/a.mp3", 320_000, None, f()),
+            make_entry("/music/mp3/a.mp3", 320_000, None, f()),
+            make_entry("/music/a.mp3", 128_000, None, f()),
+            make_entry("/music/flac/a.flac", 200_000, None, f()),
+        ];
+        assert_eq!(
+            get_paths(&find_groups(&entries)),
+            vec![(
+                DuplicateKind::Recording,
+                vec![
+                    "/music/flac/a.flac",
+                    "/music/mp3/a.mp3",
+                    "/music/mp3/high/a.mp3",
+                    "/music/a.mp3"
+                ]
+            )]
+        );
+    }
+}
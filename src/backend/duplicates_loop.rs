@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::mem;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::backend::duplicates::{self, Fingerprint};
+use crate::backend::duplicates_messages::{Event, Request};
+use crate::backend::library_index::IndexEntry;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+pub fn run(request_receiver: Receiver<Request>, event_sender: Sender<Event>) {
+    // everything computed so far, for the entries copied before their results reached the index
+    let mut computed: HashMap<PathBuf, IndexEntry> = HashMap::new();
+    loop {
+        match request_receiver.recv() {
+            Ok(Request::Find {
+                id,
+                entries,
+                cancel,
+            }) => find(id, entries, &cancel, &mut computed, &event_sender),
+            Err(e) => {
+                println!("Error in duplicates thread: {e:?}");
+                exit(1);
+            }
+        }
+    }
+}
+
+fn find(
+    id: u64,
+    mut entries: Vec<IndexEntry>,
+    cancel: &AtomicBool,
+    computed: &mut HashMap<PathBuf, IndexEntry>,
+    event_sender: &Sender<Event>,
+) {
+    // a newer search was asked for while this one was waiting
+    if cancel.load(Ordering::Relaxed) {
+        println!("[DUPLICATES] search {id} cancelled");
+        return;
+    }
+    for e in &mut entries {
+        match computed.get(&e.path) {
+            Some(c) if c.modified == e.modified && c.size == e.size => {
+                e.content_hash = e.content_hash.or(c.content_hash);
+                e.fingerprint = e.fingerprint.take().or_else(|| c.fingerprint.clone());
+            }
+            _ => {}
+        }
+    }
+
+    // only files sharing their size with another one can be exact copies
+    let mut sizes: HashMap<u64, usize> = HashMap::new();
+    for e in &entries {
+        *sizes.entry(e.size).or_default() += 1;
+    }
+    let needs_hash = |e: &IndexEntry| e.content_hash.is_none() && sizes[&e.size] > 1;
+
+    let total = entries
+        .iter()
+        .filter(|e| needs_hash(e) || e.fingerprint.is_none())
+        .count();
+    println!(
+        "[DUPLICATES] search {id}: {} tracks, {total} to read",
+        entries.len()
+    );
+    let mut done = 0;
+    let mut batch = vec![];
+    let mut last_sent = Instant::now();
+
+    for e in &mut entries {
+        if !needs_hash(e) && e.fingerprint.is_some() {
+            continue;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            // what was read so far is still cached
+            println!("[DUPLICATES] search {id} cancelled");
+            send_computed(mem::take(&mut batch), event_sender);
+            return;
+        }
+        if needs_hash(e) {
+            e.content_hash = duplicates::hash_content(&e.path);
+        }
+        if e.fingerprint.is_none() {
+            e.fingerprint = Some(Fingerprint::compute(&e.path));
+        }
+        computed.insert(e.path.clone(), e.clone());
+        batch.push(e.clone());
+        done += 1;
+        // sent as it goes, so that quitting midway keeps what was read
+        if last_sent.elapsed() >= PROGRESS_INTERVAL {
+            send_computed(mem::take(&mut batch), event_sender);
+            event_sender
+                .send(Event::Progress { id, done, total })
+                .unwrap();
+            last_sent = Instant::now();
+        }
+    }
+
+    send_computed(batch, event_sender);
+    let report = duplicates::find_groups(&entries);
+    println!("[DUPLICATES] {} groups found", report.groups.len());
+    event_sender.send(Event::Found(id, report)).unwrap();
+}
+
+fn send_computed(entries: Vec<IndexEntry>, event_sender: &Sender<Event>) {
+    if !entries.is_empty() {
+        event_sender.send(Event::Computed(entries)).unwrap();
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::backend::library_index::IndexEntry;
+use crate::duplicate_report::DuplicateReport;
+
+pub(crate) enum Request {
+    // compares the tracks, until done or `cancel` is set
+    Find {
+        id: u64,
+        entries: Vec<IndexEntry>,
+        cancel: Arc<AtomicBool>,
+    },
+}
+
+pub(crate) enum Event {
+    Progress { id: u64, done: usize, total: usize },
+    Computed(Vec<IndexEntry>), // entries given a hash or a fingerprint, to be cached in the index
+    Found(u64, DuplicateReport),
+}
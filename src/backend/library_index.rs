@@ -0,0 +1,747 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{read_dir, File, Metadata};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use symphonia::core::meta::StandardTagKey;
+
+use crate::audio_format::{self, AudioFormat};
+use crate::backend::duplicates::Fingerprint;
+use crate::backend::loader_loop;
+use crate::backend::scan_rules::{IgnoreFiles, ScanRules, IGNORE_FILE_NAME};
+use crate::backend::track_source::TrackSource;
+use crate::music_dir_creation_error::MusicDirCreationError;
+use crate::scan_report::{ScanProblemKind, ScanReport};
+use crate::LIBRARY_INDEX_RELATIVE_PATH;
+
+/// A file seen during a scan. Files that can't be decoded are kept too (with no format),
+/// so that they are not probed again until they change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    pub size: u64,
+    pub format: Option<AudioFormat>,
+    pub name: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub duration: Option<Duration>,
+    // computed on demand when looking for duplicates
+    #[serde(default)]
+    pub content_hash: Option<u64>,
+    #[serde(default)]
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl IndexEntry {
+    fn new(path: PathBuf, modified: SystemTime, size: u64) -> Self {
+        let mut entry = Self {
+            path,
+            modified,
+            size,
+            format: None,
+            name: None,
+            artist: None,
+            album: None,
+            track_number: None,
+            disc_number: None,
+            duration: None,
+            content_hash: None,
+            fingerprint: None,
+        };
+
+        if audio_format::is_skipped(&entry.path) {
+            return entry;
+        }
+        let Some(mut probed) = audio_format::probe(&entry.path) else {
+            return entry;
+        };
+        entry.format = audio_format::get_format(&probed);
+        if entry.format.is_none() {
+            return entry;
+        }
+        loader_loop::for_each_metadata_revision(&mut probed, |revision| {
+            let tag = |key| loader_loop::get_tag(revision, key);
+            entry.name = tag(StandardTagKey::TrackTitle).or(entry.name.take());
+            entry.artist = tag(StandardTagKey::Artist).or(entry.artist.take());
+            entry.album = tag(StandardTagKey::Album).or(entry.album.take());
+            entry.track_number =
+                parse_number(tag(StandardTagKey::TrackNumber)).or(entry.track_number);
+            entry.disc_number = parse_number(tag(StandardTagKey::DiscNumber)).or(entry.disc_number);
+        });
+        // the one the player shows, without the encoder delay and padding
+        entry.duration = TrackSource::new(probed).and_then(|s| s.total_duration());
+        entry
+    }
+
+    fn is_up_to_date(&self, modified: SystemTime, size: u64) -> bool {
+        self.modified == modified && self.size == size
+    }
+}
+
+// "3" or "3/12"
+fn parse_number(tag: Option<String>) -> Option<u32> {
+    tag?.split('/').next()?.trim().parse().ok()
+}
+
+// bumped when the entries gain data that needs the files to be probed again
+const INDEX_VERSION: u32 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LibraryIndex {
+    #[serde(default)]
+    version: u32,
+    entries: Vec<IndexEntry>,
+    #[serde(skip)]
+    positions: HashMap<PathBuf, usize>, // path -> position in entries
+}
+
+impl Default for LibraryIndex {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            entries: vec![],
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl LibraryIndex {
+    /// Brings the entries under `root` up to date with the disk.
+    /// Only new or changed files are probed, entries of files that disappeared are dropped.
+    /// Entries that can't be read are listed in the returned report, and their indexed
+    /// entries are kept as they are.
+    /// `on_step` is called for every folder and file, the scan is cancelled when it returns false.
+    pub fn rescan(
+        &mut self,
+        root: &Path,
+        rules: &ScanRules,
+        on_step: &mut dyn FnMut(ScanStep) -> bool,
+    ) -> Result<ScanReport, MusicDirCreationError> {
+        match root.metadata() {
+            Ok(metadata) if !metadata.is_dir() => {
+                return Err(MusicDirCreationError::NotDir(root.to_path_buf()));
+            }
+            Ok(_) => {}
+            Err(e) => return Err(MusicDirCreationError::from_io(root.to_path_buf(), &e)),
+        }
+
+        let mut scan = Scan::new(rules, root, on_step);
+        let ignore_files = IgnoreFiles::from_root(root, root);
+        if let Err(e) = self.scan_dir(root, &ignore_files, 0, &mut scan) {
+            eprintln!("Error in reading dir {}: {e}", root.display());
+            return Err(MusicDirCreationError::from_io(root.to_path_buf(), &e));
+        }
+        if scan.cancelled {
+            // the entries not reached yet are not dropped
+            println!("[INDEX] scan of {} cancelled", root.display());
+            return Err(MusicDirCreationError::Cancelled);
+        }
+        let seen = scan.seen.len();
+        let dropped = self.drop_unseen(&[(root.to_path_buf(), scan.seen)]);
+
+        println!(
+            "[INDEX] {seen} files under {}: {} probed, {dropped} dropped, {} problems",
+            root.display(),
+            scan.probed,
+            scan.report.problems.len()
+        );
+        Ok(scan.report)
+    }
+
+    /// Updates the entries of the given files and folders, e.g. after the watcher saw them change.
+    /// Returns how many tracks were added and removed.
+    pub fn update_paths(
+        &mut self,
+        paths: &[PathBuf],
+        roots: &[PathBuf],
+        rules: &ScanRules,
+    ) -> (usize, usize) {
+        // an ignore file changes what is scanned in its whole folder
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|p| match p.parent() {
+                Some(parent) if p.ends_with(IGNORE_FILE_NAME) => parent.to_path_buf(),
+                _ => p.clone(),
+            })
+            .collect();
+        let tracks_before = self.get_tracks_under(&paths);
+
+        // dropped all at once at the end, the positions stay valid meanwhile
+        let mut scanned: Vec<(PathBuf, HashSet<PathBuf>)> = vec![];
+        for path in &paths {
+            let Some(root) = roots.iter().find(|r| path.starts_with(r)) else {
+                continue;
+            };
+            let metadata = path.metadata();
+            let is_dir = metadata.as_ref().is_ok_and(|m| m.is_dir());
+            match metadata {
+                Ok(_) if rules.check_from_root(root, path, is_dir).is_some() => {
+                    scanned.push((path.clone(), HashSet::new()));
+                }
+                Ok(_) if is_dir => {
+                    let ignore_files = IgnoreFiles::from_root(root, path);
+                    let depth = path
+                        .strip_prefix(root)
+                        .map_or(0, |p| p.components().count());
+                    let mut on_step = |_: ScanStep| true;
+                    let mut scan = Scan::new(rules, path, &mut on_step);
+                    if let Err(e) = self.scan_dir(path, &ignore_files, depth, &mut scan) {
+                        self.keep_under(path, &mut scan);
+                        scan.report.add_io_error(path.clone(), &e);
+                    }
+                    for problem in &scan.report.problems {
+                        println!("[INDEX] {problem}");
+                    }
+                    scanned.push((path.clone(), scan.seen));
+                }
+                Ok(metadata) => {
+                    self.update_file(path.clone(), &metadata);
+                }
+                Err(_) => {
+                    // deleted or moved away
+                    scanned.push((path.clone(), HashSet::new()));
+                }
+            }
+        }
+        self.drop_unseen(&scanned);
+
+        let tracks_after = self.get_tracks_under(&paths);
+        let added = tracks_after.difference(&tracks_before).count();
+        let removed = tracks_before.difference(&tracks_after).count();
+        (added, removed)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&IndexEntry> {
+        self.positions.get(path).map(|&i| &self.entries[i])
+    }
+
+    /// Playable entries under the given roots.
+    pub fn get_tracks_in(&self, roots: &[PathBuf]) -> Vec<&IndexEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.format.is_some())
+            .filter(|e| roots.iter().any(|r| e.path.starts_with(r)))
+            .collect()
+    }
+
+    /// Replaces an entry with a copy holding more data, unless the file changed meanwhile.
+    pub fn update_if_unchanged(&mut self, entry: IndexEntry) {
+        if let Some(&i) = self.positions.get(&entry.path) {
+            if self.entries[i].is_up_to_date(entry.modified, entry.size) {
+                self.entries[i] = entry;
+            }
+        }
+    }
+
+    /// Copies the hashes and fingerprints of `other` missing here, for the files that didn't change.
+    pub fn keep_computed(&mut self, other: &LibraryIndex) {
+        for computed in &other.entries {
+            if computed.content_hash.is_none() && computed.fingerprint.is_none() {
+                continue;
+            }
+            let Some(&i) = self.positions.get(&computed.path) else {
+                continue;
+            };
+            let entry = &mut self.entries[i];
+            if entry.is_up_to_date(computed.modified, computed.size) {
+                entry.content_hash = entry.content_hash.or(computed.content_hash);
+                entry.fingerprint = entry
+                    .fingerprint
+                    .take()
+                    .or_else(|| computed.fingerprint.clone());
+            }
+        }
+    }
+
+    /// Adds or replaces the entry of `entry.path`.
+    pub fn insert(&mut self, entry: IndexEntry) {
+        match self.positions.get(&entry.path) {
+            Some(&i) => self.entries[i] = entry,
+            None => {
+                self.positions
+                    .insert(entry.path.clone(), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Paths of the indexed tracks under `root` whose format is enabled and that are long enough.
+    pub fn get_track_paths(
+        &self,
+        root: &Path,
+        formats: &[AudioFormat],
+        rules: &ScanRules,
+    ) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|e| e.path.starts_with(root))
+            .filter(|e| e.format.is_some_and(|f| formats.contains(&f)))
+            .filter(|e| rules.check_duration(e.duration).is_none())
+            .map(|e| e.path.clone())
+            .collect()
+    }
+
+    fn get_tracks_under(&self, paths: &[PathBuf]) -> HashSet<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|e| e.format.is_some())
+            .filter(|e| paths.iter().any(|p| e.path.starts_with(p)))
+            .map(|e| e.path.clone())
+            .collect()
+    }
+
+    /// Scans the folder `path`, `depth` levels below its root. Only failing to read `path`
+    /// itself is an error, the entries that can't be read are added to the report.
+    fn scan_dir(
+        &mut self,
+        path: &Path,
+        ignore_files: &IgnoreFiles,
+        depth: usize,
+        scan: &mut Scan,
+    ) -> io::Result<()> {
+        if !(scan.on_step)(ScanStep::Dir(path)) {
+            scan.cancelled = true;
+        }
+        for entry in read_dir(path)? {
+            if scan.cancelled {
+                return Ok(());
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    scan.report.add_io_error(path.to_path_buf(), &e);
+                    // the listing is incomplete, the entries missing from it may still be there
+                    self.keep_under(path, scan);
+                    continue;
+                }
+            };
+            let path_buf = entry.path();
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            if is_symlink && !scan.rules.follows_symlinks() {
+                continue;
+            }
+            let metadata = match path_buf.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) if is_symlink => {
+                    scan.report.add(path_buf, ScanProblemKind::BrokenLink);
+                    continue;
+                }
+                Err(e) => {
+                    self.keep_under(&path_buf, scan);
+                    scan.report.add_io_error(path_buf, &e);
+                    continue;
+                }
+            };
+            if scan
+                .rules
+                .check(&path_buf)
+                .or_else(|| ignore_files.check(&path_buf, metadata.is_dir()))
+                .is_some()
+            {
+                // excluded files are not seen, so they are dropped from the index
+                continue;
+            }
+            if metadata.is_dir() {
+                if depth >= scan.rules.max_depth() {
+                    scan.report.add(path_buf, ScanProblemKind::TooDeep);
+                    continue;
+                }
+                if !scan.visit(&path_buf, &metadata) {
+                    scan.report.add(path_buf, ScanProblemKind::Revisited);
+                    continue;
+                }
+                let ignore_files = ignore_files.enter(&path_buf);
+                if let Err(e) = self.scan_dir(&path_buf, &ignore_files, depth + 1, scan) {
+                    self.keep_under(&path_buf, scan);
+                    scan.report.add_io_error(path_buf, &e);
+                }
+                continue;
+            }
+            scan.seen.insert(path_buf.clone());
+            let probed = self.update_file(path_buf.clone(), &metadata);
+            if probed {
+                scan.probed += 1;
+            }
+            let entry = &self.entries[self.positions[&path_buf]];
+            if !(scan.on_step)(ScanStep::File(entry, probed)) {
+                scan.cancelled = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Probes the file if it is new or changed. Returns whether it was probed.
+    fn update_file(&mut self, path: PathBuf, metadata: &Metadata) -> bool {
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let size = metadata.len();
+
+        match self.positions.get(&path) {
+            Some(&i) if self.entries[i].is_up_to_date(modified, size) => false,
+            Some(&i) => {
+                self.entries[i] = IndexEntry::new(path, modified, size);
+                true
+            }
+            None => {
+                self.positions.insert(path.clone(), self.entries.len());
+                self.entries.push(IndexEntry::new(path, modified, size));
+                true
+            }
+        }
+    }
+
+    /// Counts the entries under `path` as seen, so that a folder that failed to read,
+    /// e.g. denied or on a flaky network drive, keeps its tracks until it reads again.
+    fn keep_under(&self, path: &Path, scan: &mut Scan) {
+        let kept = self.entries.iter().filter(|e| e.path.starts_with(path));
+        scan.seen.extend(kept.map(|e| e.path.clone()));
+    }
+
+    /// Drops the entries under each path that are not in its set of seen files.
+    /// Returns how many were dropped.
+    fn drop_unseen(&mut self, scanned: &[(PathBuf, HashSet<PathBuf>)]) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| {
+            !scanned
+                .iter()
+                .any(|(path, seen)| e.path.starts_with(path) && !seen.contains(&e.path))
+        });
+        if self.entries.len() != before {
+            self.rebuild_positions();
+        }
+        before - self.entries.len()
+    }
+
+    fn rebuild_positions(&mut self) {
+        self.positions = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.path.clone(), i))
+            .collect();
+    }
+}
+
+/// What the scan is doing.
+pub enum ScanStep<'a> {
+    Dir(&'a Path),
+    File(&'a IndexEntry, bool), // whether it was probed, i.e. new or changed
+}
+
+/// State of one scan.
+struct Scan<'a> {
+    rules: &'a ScanRules,
+    on_step: &'a mut dyn FnMut(ScanStep) -> bool,
+    cancelled: bool,
+    seen: HashSet<PathBuf>,
+    visited: HashSet<DirId>, // folders already scanned, to not loop through symlinks
+    probed: usize,
+    report: ScanReport,
+}
+
+impl<'a> Scan<'a> {
+    fn new(
+        rules: &'a ScanRules,
+        start: &Path,
+        on_step: &'a mut dyn FnMut(ScanStep) -> bool,
+    ) -> Self {
+        let mut scan = Self {
+            rules,
+            on_step,
+            cancelled: false,
+            seen: HashSet::new(),
+            visited: HashSet::new(),
+            probed: 0,
+            report: ScanReport::default(),
+        };
+        if let Ok(metadata) = start.metadata() {
+            scan.visit(start, &metadata);
+        }
+        scan
+    }
+
+    /// Returns false if the folder was already visited.
+    fn visit(&mut self, path: &Path, metadata: &Metadata) -> bool {
+        match get_dir_id(path, metadata) {
+            Some(id) => self.visited.insert(id),
+            None => true,
+        }
+    }
+}
+
+#[cfg(unix)]
+type DirId = (u64, u64); // (device, inode)
+
+#[cfg(unix)]
+fn get_dir_id(_path: &Path, metadata: &Metadata) -> Option<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(not(unix))]
+fn get_dir_id(path: &Path, _metadata: &Metadata) -> Option<DirId> {
+    path.canonicalize().ok()
+}
+
+pub fn read(dir: &Path) -> LibraryIndex {
+    let path = dir.join(LIBRARY_INDEX_RELATIVE_PATH);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            return LibraryIndex::default();
+        }
+    };
+    match serde_json::from_reader::<_, LibraryIndex>(BufReader::new(file)) {
+        Ok(index) if index.version != INDEX_VERSION => {
+            eprintln!(
+                "{} is outdated, the library will be scanned from scratch.",
+                path.display()
+            );
+            LibraryIndex::default()
+        }
+        Ok(mut index) => {
+            index.rebuild_positions();
+            index
+        }
+        Err(e) => {
+            eprintln!("Error in parsing {}: {e}", path.display());
+            eprintln!("The library will be scanned from scratch.");
+            LibraryIndex::default()
+        }
+    }
+}
+
+/// The index is only a cache, so failing to write it is not fatal.
+pub fn write(dir: &Path, index: &LibraryIndex) {
+    let path = dir.join(LIBRARY_INDEX_RELATIVE_PATH);
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create file '{}': {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = serde_json::to_writer(BufWriter::new(file), index) {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    use std::os::unix::fs::symlink;
+
+    use crate::backend::track_source::tests::write_wav;
+    use crate::settings::{Settings, SymlinkPolicy};
+
+    // an empty root in its own folder
+    fn make_root(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rustify-index-tests-{}/{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    // a one second track
+    fn add_track(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy(write_wav("index-track.wav", 1, 8000, &[0.0; 8000]), path).unwrap();
+    }
+
+    fn scan(root: &Path, rules: &ScanRules) -> (LibraryIndex, ScanReport) {
+        let mut index = LibraryIndex::default();
+        let report = index.rescan(root, rules, &mut |_| true).unwrap();
+        (index, report)
+    }
+
+    // the indexed tracks, relative to the root
+    fn get_tracks(index: &LibraryIndex, root: &Path) -> Vec<String> {
+        for (i, e) in index.entries.iter().enumerate() {
+            assert_eq!(index.positions[&e.path], i);
+        }
+        assert_eq!(index.positions.len(), index.entries.len());
+        let mut tracks: Vec<String> = index
+            .get_tracks_in(&[root.to_path_buf()])
+            .iter()
+            .map(|e| e.path.strip_prefix(root).unwrap().display().to_string())
+            .collect();
+        tracks.sort();
+        tracks
+    }
+
+    fn make_rules(symlinks: SymlinkPolicy, max_scan_depth: u32) -> ScanRules {
+        let mut settings = Settings::default();
+        settings.symlinks = symlinks;
+        settings.max_scan_depth = max_scan_depth;
+        ScanRules::new(&settings)
+    }
+
+    // the problems of the report, relative to the root
+    fn get_problems(report: &ScanReport, root: &Path) -> Vec<(String, ScanProblemKind)> {
+        report
+            .problems
+            .iter()
+            .map(|p| {
+                let path = p.path.strip_prefix(root).unwrap().display().to_string();
+                (path, p.kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_symlink_loop_is_scanned_once() {
+        let root = make_root("symlink-loop");
+        add_track(&root.join("a.wav"));
+        add_track(&root.join("album/1.wav"));
+        symlink(&root, root.join("album/loop")).unwrap();
+        let (index, report) = scan(&root, &make_rules(SymlinkPolicy::Follow, 32));
+        assert_eq!(get_tracks(&index, &root), ["a.wav", "album/1.wav"]);
+        assert_eq!(
+            get_problems(&report, &root),
+            [("album/loop".to_string(), ScanProblemKind::Revisited)]
+        );
+    }
+
+    #[test]
+    fn links_out_of_the_root_are_followed_unless_skipped() {
+        let root = make_root("symlink-out");
+        let outside = root.with_file_name("outside");
+        add_track(&root.join("a.wav"));
+        add_track(&outside.join("1.wav"));
+        symlink(&outside, root.join("linked")).unwrap();
+        symlink(outside.join("1.wav"), root.join("b.wav")).unwrap();
+        symlink(root.join("gone"), root.join("broken")).unwrap();
+
+        let (index, report) = scan(&root, &make_rules(SymlinkPolicy::Follow, 32));
+        assert_eq!(
+            get_tracks(&index, &root),
+            ["a.wav", "b.wav", "linked/1.wav"]
+        );
+        assert_eq!(
+            get_problems(&report, &root),
+            [("broken".to_string(), ScanProblemKind::BrokenLink)]
+        );
+
+        let (index, report) = scan(&root, &make_rules(SymlinkPolicy::Skip, 32));
+        assert_eq!(get_tracks(&index, &root), ["a.wav"]);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn folders_past_the_depth_limit_are_not_scanned() {
+        let root = make_root("depth");
+        for name in ["0.wav", "a/1.wav", "a/b/2.wav", "a/b/c/3.wav"] {
+            add_track(&root.join(name));
+        }
+        let (index, report) = scan(&root, &make_rules(SymlinkPolicy::Follow, 2));
+        assert_eq!(get_tracks(&index, &root), ["0.wav", "a/1.wav", "a/b/2.wav"]);
+        assert_eq!(
+            get_problems(&report, &root),
+            [("a/b/c".to_string(), ScanProblemKind::TooDeep)]
+        );
+        // the same limit when updating a folder below the root
+        let (mut index, _) = scan(&root, &make_rules(SymlinkPolicy::Follow, 32));
+        let rules = make_rules(SymlinkPolicy::Follow, 2);
+        let (changed, roots) = ([root.join("a/b")], [root.clone()]);
+        assert_eq!(index.update_paths(&changed, &roots, &rules), (0, 1));
+        assert_eq!(get_tracks(&index, &root), ["0.wav", "a/1.wav", "a/b/2.wav"]);
+    }
+
+    #[test]
+    fn folders_that_fail_to_read_keep_their_tracks() {
+        let root = make_root("failing");
+        for name in ["a.wav", "album/1.wav", "album/2.wav", "other/1.wav"] {
+            add_track(&root.join(name));
+        }
+        let rules = make_rules(SymlinkPolicy::Follow, 32);
+        let (mut index, _) = scan(&root, &rules);
+
+        // removed after being listed: reading it fails, as it would if denied
+        let album = root.join("album");
+        let mut on_step = |step: ScanStep| {
+            if matches!(step, ScanStep::Dir(dir) if dir == album) {
+                fs::remove_dir_all(&album).unwrap();
+            }
+            true
+        };
+        let report = index.rescan(&root, &rules, &mut on_step).unwrap();
+        assert_eq!(
+            get_problems(&report, &root),
+            [(
+                "album".to_string(),
+                ScanProblemKind::Io(io::ErrorKind::NotFound)
+            )]
+        );
+        assert_eq!(
+            get_tracks(&index, &root),
+            ["a.wav", "album/1.wav", "album/2.wav", "other/1.wav"]
+        );
+
+        // once read again, the folder is gone
+        let report = index.rescan(&root, &rules, &mut |_| true).unwrap();
+        assert!(report.problems.is_empty());
+        assert_eq!(get_tracks(&index, &root), ["a.wav", "other/1.wav"]);
+    }
+
+    #[test]
+    fn updated_paths_add_remove_and_rename_tracks() {
+        let root = make_root("update-files");
+        let rules = ScanRules::new(&Settings::default());
+        for name in ["a.wav", "b.wav", "c.wav"] {
+            add_track(&root.join(name));
+        }
+        let (mut index, _) = scan(&root, &rules);
+        let roots = [root.clone()];
+
+        add_track(&root.join("d.wav"));
+        fs::remove_file(root.join("a.wav")).unwrap();
+        fs::rename(root.join("b.wav"), root.join("e.wav")).unwrap();
+        let changed = ["d.wav", "a.wav", "b.wav", "e.wav"].map(|p| root.join(p));
+        assert_eq!(index.update_paths(&changed, &roots, &rules), (2, 2));
+        assert_eq!(get_tracks(&index, &root), ["c.wav", "d.wav", "e.wav"]);
+        assert_eq!(
+            index.get(&root.join("e.wav")).unwrap().duration,
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn updated_folders_are_scanned_again() {
+        let root = make_root("update-folders");
+        let rules = ScanRules::new(&Settings::default());
+        for name in ["a.wav", "album/1.wav", "album/2.wav", "other/1.wav"] {
+            add_track(&root.join(name));
+        }
+        let (mut index, _) = scan(&root, &rules);
+        let roots = [root.clone()];
+
+        // removed entirely, and moved
+        fs::remove_dir_all(root.join("album")).unwrap();
+        fs::rename(root.join("other"), root.join("moved")).unwrap();
+        add_track(&root.join("moved/2.wav"));
+        let changed = ["album", "other", "moved"].map(|p| root.join(p));
+        assert_eq!(index.update_paths(&changed, &roots, &rules), (2, 3));
+        assert_eq!(
+            get_tracks(&index, &root),
+            ["a.wav", "moved/1.wav", "moved/2.wav"]
+        );
+
+        // a new ignore file applies to its whole folder
+        fs::write(root.join("moved").join(IGNORE_FILE_NAME), "1.wav\n").unwrap();
+        let changed = [root.join("moved").join(IGNORE_FILE_NAME)];
+        assert_eq!(index.update_paths(&changed, &roots, &rules), (0, 1));
+        assert_eq!(get_tracks(&index, &root), ["a.wav", "moved/2.wav"]);
+    }
+}
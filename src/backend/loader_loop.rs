@@ -6,14 +6,13 @@ use std::sync::Arc;
 use crossbeam_channel::{Receiver, Sender};
 use eframe::egui::ColorImage;
 use image::RgbaImage;
-use rodio::{Decoder, Source};
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::{MetadataOptions, StandardTagKey, Visual};
-use symphonia::core::probe::Hint;
-use symphonia::default::get_probe;
+use rodio::Source;
+use symphonia::core::meta::{MetadataRevision, StandardTagKey, Visual};
+use symphonia::core::probe::ProbeResult;
 
+use crate::audio_format;
 use crate::backend::loader_messages::{Request, Response};
+use crate::backend::track_source::TrackSource;
 use crate::image_utils;
 use crate::track_metadata::TrackMetaData;
 
@@ -35,11 +34,16 @@ pub fn run(request_receiver: Receiver<Request>, response_sender: Sender<Response
 }
 
 fn handle_request(path: PathBuf, response_sender: &Sender<Response>) {
-    // metadata
-    let file = File::open(&path).unwrap();
-    let source = Decoder::new(file).unwrap();
-    let duration = source.total_duration();
-    let mut metadata = match get_track_metadata(&path) {
+    let Some(file) = open_track(&path, response_sender) else {
+        return;
+    };
+    // one probe gives both the metadata and the source
+    let Some(mut probed) = audio_format::probe_file(file, &path) else {
+        println!("Loader: cannot probe {path:?}");
+        response_sender.send(Response::NotFound(path)).unwrap();
+        return;
+    };
+    let mut metadata = match get_track_metadata(&mut probed, &path) {
         None => {
             let mut m = TrackMetaData::default();
             if let Some(name) = path.file_name() {
@@ -51,65 +55,94 @@ fn handle_request(path: PathBuf, response_sender: &Sender<Response>) {
         }
         Some(m) => m,
     };
-    metadata.duration = duration;
+    let Some(source) = TrackSource::new(probed) else {
+        println!("Loader: cannot decode {path:?}");
+        response_sender.send(Response::NotFound(path)).unwrap();
+        return;
+    };
+    // without the encoder delay and padding, like what plays
+    metadata.duration = source.total_duration();
     let metadata = Arc::new(metadata);
 
-    // file (again)
-    let file = File::open(&path).unwrap();
-
     response_sender
-        .send(Response::Track(file, metadata))
+        .send(Response::Track(path.clone(), source, metadata))
         .unwrap();
     println!("Loader: Load response sent ({path:?})");
 }
 
-pub fn get_track_metadata(path: &Path) -> Option<TrackMetaData> {
-    let file = File::open(path).ok()?;
+/// Opens the file, or tells the main thread it is gone (e.g. deleted after being picked).
+fn open_track(path: &Path, response_sender: &Sender<Response>) -> Option<File> {
+    match File::open(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            println!("Loader: cannot open {path:?}: {e}");
+            response_sender
+                .send(Response::NotFound(path.to_path_buf()))
+                .unwrap();
+            None
+        }
+    }
+}
+
+fn get_track_metadata(probed: &mut ProbeResult, path: &Path) -> Option<TrackMetaData> {
+    let mut track = TrackMetaData::default();
+    let mut found = false;
+    let mut image = None;
 
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    for_each_metadata_revision(probed, |revision| {
+        found = true;
 
-    let probe = get_probe();
-    let hint = Hint::new();
+        // read tags
+        if let Some(album) = get_tag(revision, StandardTagKey::Album) {
+            track.album = album;
+        }
+        if let Some(artist) = get_tag(revision, StandardTagKey::Artist) {
+            track.artist = artist;
+        }
+        if let Some(name) = get_tag(revision, StandardTagKey::TrackTitle) {
+            track.name = name;
+        }
 
-    // probe file
-    let mut probed = probe
-        .format(
-            &hint,
-            mss,
-            &FormatOptions::default(),
-            &MetadataOptions::default(),
-        )
-        .map_err(|e| format!("Failed to probe format: {}", e))
-        .ok()?;
+        // read cover image
+        if image.is_none() {
+            image = revision
+                .visuals()
+                .first()
+                .and_then(get_color_image_from_visual);
+        }
+    });
 
-    // get metadata
-    let binding = probed.metadata.get()?;
+    if !found {
+        return None;
+    }
 
-    let current_metadata = binding.current()?;
+    track.image = image.or_else(|| get_color_image_from_track_path(path));
 
-    let mut track = TrackMetaData::default();
+    Some(track)
+}
 
-    // read tags
-    for tag in current_metadata.tags() {
-        if let Some(std_key) = tag.std_key {
-            let value = tag.value.to_string();
-            match std_key {
-                StandardTagKey::Album => track.album = value,
-                StandardTagKey::Artist => track.artist = value,
-                StandardTagKey::TrackTitle => track.name = value,
-                _ => {}
-            }
+/// Calls `f` on the metadata found while probing (e.g. ID3v2), then on the container one
+/// (e.g. Vorbis comments, MP4 atoms).
+pub(crate) fn for_each_metadata_revision(
+    probed: &mut ProbeResult,
+    mut f: impl FnMut(&MetadataRevision),
+) {
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            f(revision);
         }
     }
-
-    // read cover image
-    if let Some(v) = current_metadata.visuals().first() {
-        track.image = get_color_image_from_visual(v);
-    } else {
-        track.image = get_color_image_from_track_path(path);
+    if let Some(revision) = probed.format.metadata().current() {
+        f(revision);
     }
+}
 
-    Some(track)
+pub(crate) fn get_tag(revision: &MetadataRevision, key: StandardTagKey) -> Option<String> {
+    revision
+        .tags()
+        .iter()
+        .find(|tag| tag.std_key == Some(key))
+        .map(|tag| tag.value.to_string())
 }
 
 fn get_color_image_from_visual(v: &Visual) -> Option<ColorImage> {
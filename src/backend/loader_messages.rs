@@ -1,7 +1,7 @@
-use std::fs::File;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::backend::track_source::TrackSource;
 use crate::track_metadata::TrackMetaData;
 
 #[derive(Clone)]
@@ -10,6 +10,6 @@ pub(crate) enum Request {
 }
 
 pub(crate) enum Response {
-    Track(File, Arc<TrackMetaData>),
-    NotFound(PathBuf),
+    Track(PathBuf, TrackSource, Arc<TrackMetaData>),
+    NotFound(PathBuf), // gone, or no longer playable
 }
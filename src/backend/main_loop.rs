@@ -1,50 +1,190 @@
+use std::collections::{HashSet, VecDeque};
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crossbeam_channel::{select, unbounded, Receiver, RecvError, Sender};
+use crossbeam_channel::{select, tick, unbounded, Receiver, RecvError, Sender};
 use eframe::egui::Context;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
+use crate::backend::library_index::LibraryIndex;
 use crate::backend::music_dir::MusicDir;
-use crate::backend::{loader_loop, loader_messages, player_loop, player_messages};
-use crate::settings::Settings;
+use crate::backend::player_messages::{Fades, Placement};
+use crate::backend::replay::{Replay, ShuffleState};
+use crate::backend::scan_rules::ScanRules;
+use crate::backend::session::Session;
+use crate::backend::shuffle_bag::ShuffleBag;
+use crate::backend::shuffle_history::ShuffleHistory;
+use crate::backend::spacing::Spacing;
+use crate::backend::track_stats::TrackStats;
+use crate::backend::{
+    duplicates_loop, duplicates_messages, library_index, loader_loop, loader_messages, player_loop,
+    player_messages, replay, scanner_loop, scanner_messages, sequential, session, shuffle_bag,
+    shuffle_history, track_stats, watcher_loop, watcher_messages,
+};
+use crate::cli_args::CliArgs;
+use crate::duplicate_report::DuplicateReport;
+use crate::music_dir_creation_error::MusicDirCreationError;
+use crate::scan_report::ScanReport;
+use crate::settings::{PlayOrder, Repeat, Settings};
 use crate::{messages, settings};
 
-const TRACK_QUEUE_FILL_UNTIL: u8 = 3;
+// counted for the tracks whose container doesn't tell their duration
+const UNKNOWN_TRACK_DURATION: Duration = Duration::from_secs(180);
+const HISTORY_LEN: usize = 100;
+// tracks asked for by the user are not queued beyond this
+const MAX_QUEUE_LEN: usize = 200;
+// going back after this restarts the current track instead
+const RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+// the position in the current track is saved this often while it plays
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+// writing the whole index after every batch of hashes would take longer than computing them
+const INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+// the changes seen by the watcher are saved once none came for this long
+const INDEX_WRITE_DELAY: Duration = Duration::from_secs(2);
+// the shuffle history and bag are saved this long after a pick, not after every one
+const SHUFFLE_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+// how often the changes waiting to be saved are checked
+const SAVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_FADE_SECS: f32 = 12.0;
 
 struct ThreadData {
     settings: Settings,
-    root_music_dir: Option<MusicDir>,
-    queued_tracks: u8,
-    loading_tracks: u8,
+    library_index: LibraryIndex,
+    scan_rules: ScanRules,
+    music_dir: Option<MusicDir>,
+    queued_tracks: usize,
+    loading_tracks: usize,
     waiting_jump_response: bool,
     ctx: Option<Context>,
+    data_dir: PathBuf, // where the settings, index and state files are kept
     event_sender: Sender<messages::Event>,
     player_req_sender: Sender<player_messages::Request>,
     load_req_sender: Sender<loader_messages::Request>,
+    watcher_req_sender: Sender<watcher_messages::Request>,
+    scan_req_sender: Sender<scanner_messages::Request>,
+    duplicates_req_sender: Sender<duplicates_messages::Request>,
+    duplicates: DuplicateReport,
+    shuffle_history: ShuffleHistory,
+    shuffle_bag: ShuffleBag,
+    track_stats: TrackStats,
+    rng: StdRng, // every random pick, seeded so that a session can be replayed
+    // some when replaying a session: its picks come first, and the state stays in memory
+    // as the files belong to the live sessions
+    replayed_picks: Option<VecDeque<PathBuf>>,
+    current_track: Option<PathBuf>,
+    last_picked: Option<PathBuf>, // the sequential orders go on from it
+    skipped_album: Option<PathBuf>, // its tracks still being loaded are dropped
+    history: Vec<PathBuf>,        // played before the current track, most recent last
+    progress: Duration,           // in the current track
+    paused: bool, // asked for by the user, a new music dir doesn't resume playback then
+    going_back: Option<GoingBack>,
+    explicit_loads: Vec<(PathBuf, Placement)>, // tracks asked for by the user, being loaded
+    upcoming_durations: Vec<Option<Duration>>, // of the tracks queued in the player
+    upcoming: Vec<(PathBuf, bool)>, // queued in the player and whether asked for, for the session
+    restoring: Option<Restoring>,
+    saved_progress: Duration,       // in the session file
+    reroll_slots: Vec<usize>,       // upcoming tracks to replace once removed by the player
+    rerolls: Vec<(PathBuf, usize)>, // replacements being loaded and the place they go to
+    scan: Option<RunningScan>,
+    next_scan_id: u64,
+    duplicates_search: Option<RunningSearch>,
+    next_search_id: u64,
+    index_saved_at: Instant, // the hashes and fingerprints are saved now and then
+    index_changed_at: Option<Instant>, // the last change not saved yet
+    shuffle_changed_at: Option<Instant>, // the first pick not saved yet
+}
+
+// the last session being restored, until its first track plays
+struct Restoring {
+    current: Option<PathBuf>,
+    position: Duration,
+}
+
+struct RunningScan {
+    id: u64,
+    cancel: Arc<AtomicBool>,
+    pending_changes: Vec<PathBuf>, // changes seen by the watcher, applied after the scan
+}
+
+struct RunningSearch {
+    id: u64,
+    cancel: Arc<AtomicBool>,
 }
 
 impl ThreadData {
+    #[allow(clippy::too_many_arguments)] // one sender per thread
     fn new(
+        data_dir: PathBuf,
         settings: Settings,
+        library_index: LibraryIndex,
+        state: ShuffleState,
+        rng: StdRng,
         event_sender: Sender<messages::Event>,
         player_req_sender: Sender<player_messages::Request>,
         load_req_sender: Sender<loader_messages::Request>,
+        watcher_req_sender: Sender<watcher_messages::Request>,
+        scan_req_sender: Sender<scanner_messages::Request>,
+        duplicates_req_sender: Sender<duplicates_messages::Request>,
     ) -> Self {
         Self {
+            scan_rules: ScanRules::new(&settings),
             settings,
-            root_music_dir: None,
+            library_index,
+            music_dir: None,
             queued_tracks: 0,
             loading_tracks: 0,
             waiting_jump_response: false,
             ctx: None,
+            data_dir,
             event_sender,
             player_req_sender,
             load_req_sender,
+            watcher_req_sender,
+            scan_req_sender,
+            duplicates_req_sender,
+            duplicates: DuplicateReport::default(),
+            shuffle_history: state.history,
+            shuffle_bag: state.bag,
+            track_stats: state.stats,
+            rng,
+            replayed_picks: None,
+            current_track: None,
+            last_picked: None,
+            skipped_album: None,
+            history: Vec::new(),
+            progress: Duration::ZERO,
+            paused: false,
+            going_back: None,
+            explicit_loads: Vec::new(),
+            upcoming_durations: Vec::new(),
+            upcoming: Vec::new(),
+            restoring: None,
+            saved_progress: Duration::ZERO,
+            reroll_slots: Vec::new(),
+            rerolls: Vec::new(),
+            scan: None,
+            next_scan_id: 0,
+            duplicates_search: None,
+            next_search_id: 0,
+            index_saved_at: Instant::now(),
+            index_changed_at: None,
+            shuffle_changed_at: None,
         }
     }
 }
 
-pub fn run(request_receiver: Receiver<messages::Request>, event_sender: Sender<messages::Event>) {
+pub fn run(
+    request_receiver: Receiver<messages::Request>,
+    event_sender: Sender<messages::Event>,
+    args: CliArgs,
+) {
     // player thread
     let (player_req_sender, player_req_receiver) = unbounded::<player_messages::Request>();
     let (player_event_sender, player_event_receiver) = unbounded::<player_messages::Event>();
@@ -53,24 +193,88 @@ pub fn run(request_receiver: Receiver<messages::Request>, event_sender: Sender<m
     let (load_req_sender, load_req_receiver) = unbounded::<loader_messages::Request>();
     let (load_resp_sender, load_resp_receiver) = unbounded::<loader_messages::Response>();
 
+    // watcher thread
+    let (watcher_req_sender, watcher_req_receiver) = unbounded::<watcher_messages::Request>();
+    let (watcher_event_sender, watcher_event_receiver) = unbounded::<watcher_messages::Event>();
+
+    // scanner thread
+    let (scan_req_sender, scan_req_receiver) = unbounded::<scanner_messages::Request>();
+    let (scan_event_sender, scan_event_receiver) = unbounded::<scanner_messages::Event>();
+
+    // duplicates thread
+    let (duplicates_req_sender, duplicates_req_receiver) =
+        unbounded::<duplicates_messages::Request>();
+    let (duplicates_event_sender, duplicates_event_receiver) =
+        unbounded::<duplicates_messages::Event>();
+
+    // the json files are kept in the working folder
+    let data_dir = PathBuf::new();
+
     // read settings and send them to frontend
-    let settings = settings::read();
+    let mut settings = settings::read(&data_dir);
+
+    // record the seed and the state the random picks start from, so that the session can be replayed
+    let recorded = read_replay(args, &data_dir);
+    let replaying = recorded.is_some();
+    let replay = recorded.unwrap_or_else(|| new_replay(args, &settings, &data_dir));
+    println!("[MAIN] Shuffle seed: {}", replay.seed);
+    settings.last_seed = Some(replay.seed);
+    settings::write(&data_dir, &settings);
+    if replaying {
+        println!(
+            "[MAIN] Replaying {} picks, the shuffle state is not saved",
+            replay.picks.len()
+        );
+    } else {
+        replay::write(&data_dir, &replay);
+    }
+
     event_sender
         .send(messages::Event::NewSettings(settings.clone()))
         .expect("Error in send");
 
+    // read the library index saved by the last session
+    let library_index = library_index::read(&data_dir);
+
     // data
-    let mut data = ThreadData::new(settings, event_sender, player_req_sender, load_req_sender);
+    let mut data = ThreadData::new(
+        data_dir,
+        settings,
+        library_index,
+        replay.state,
+        StdRng::seed_from_u64(replay.seed),
+        event_sender,
+        player_req_sender,
+        load_req_sender,
+        watcher_req_sender,
+        scan_req_sender,
+        duplicates_req_sender,
+    );
 
     // spawn threads
     thread::spawn(move || loader_loop::run(load_req_receiver, load_resp_sender));
     thread::spawn(move || player_loop::run(player_req_receiver, player_event_sender));
+    thread::spawn(move || watcher_loop::run(watcher_req_receiver, watcher_event_sender));
+    thread::spawn(move || scanner_loop::run(scan_req_receiver, scan_event_sender));
+    thread::spawn(move || duplicates_loop::run(duplicates_req_receiver, duplicates_event_sender));
 
     // send change volume
     data.player_req_sender
         .send(player_messages::Request::SetVolume(data.settings.volume))
         .unwrap();
+    data.player_req_sender
+        .send(player_messages::Request::SetRepeatOne(
+            data.settings.repeat == Repeat::One,
+        ))
+        .unwrap();
+    send_fades(&data);
 
+    if replaying {
+        data.replayed_picks = Some(replay.picks.into());
+    }
+    restore_session(replay.session, &mut data);
+
+    let save_ticker = tick(SAVE_CHECK_INTERVAL);
     loop {
         select! {
             recv(request_receiver) -> res => handle_request(
@@ -84,57 +288,95 @@ pub fn run(request_receiver: Receiver<messages::Request>, event_sender: Sender<m
             recv(player_event_receiver) -> res => handle_player_event(
                 res,
                 &mut data
-            )
+            ),
+            recv(watcher_event_receiver) -> res => handle_watcher_event(
+                res,
+                &mut data
+            ),
+            recv(scan_event_receiver) -> res => handle_scan_event(
+                res,
+                &mut data
+            ),
+            recv(duplicates_event_receiver) -> res => handle_duplicates_event(
+                res,
+                &mut data
+            ),
+            recv(save_ticker) -> _ => save_pending_changes(&mut data),
         }
     }
 }
 
+/// Crossfade and skip fade from settings.json. Albums played in order stay gapless
+/// unless `gapless_albums` is off.
+fn send_fades(data: &ThreadData) {
+    let settings = &data.settings;
+    let fades = Fades {
+        crossfade: Duration::from_secs_f32(settings.crossfade_secs.clamp(0.0, MAX_FADE_SECS)),
+        skip: Duration::from_secs_f32(settings.skip_fade_secs.clamp(0.0, MAX_FADE_SECS)),
+        gapless_albums: settings.gapless_albums && settings.play_order.plays_in_order(),
+    };
+    data.player_req_sender
+        .send(player_messages::Request::SetFades(fades))
+        .unwrap();
+}
+
+/// For --replay, the last session: the seed and state it started from, and its picks.
+fn read_replay(args: CliArgs, dir: &Path) -> Option<Replay> {
+    if !args.replay || args.seed.is_some() {
+        return None;
+    }
+    let replay = replay::read(dir);
+    if replay.is_none() {
+        // e.g. recorded by a version that only saved the seed
+        eprintln!("No session to replay, starting from the saved state");
+    }
+    replay
+}
+
+/// The saved state, with the seed given on the command line, else `shuffle_seed`
+/// from settings.json, else a new one.
+fn new_replay(args: CliArgs, settings: &Settings, dir: &Path) -> Replay {
+    let seed = args
+        .seed
+        .or(settings.last_seed.filter(|_| args.replay))
+        .or(settings.shuffle_seed)
+        .unwrap_or_else(rand::random);
+    Replay {
+        seed,
+        state: ShuffleState::read(dir),
+        session: session::read(dir),
+        picks: vec![],
+    }
+}
+
 fn handle_request(res: Result<messages::Request, RecvError>, data: &mut ThreadData) {
     match res {
         Ok(req) => match req {
-            messages::Request::ChangeRoot(path) => {
-                println!("[MAIN] received ChangeRoot request");
+            messages::Request::ChangeRoots(roots) => {
+                println!("[MAIN] received ChangeRoots request");
                 // send clear
                 data.player_req_sender
                     .send(player_messages::Request::Clear)
                     .unwrap();
                 data.queued_tracks = 0;
 
-                // new music dir and load tracks
-                match MusicDir::new(path.clone()) {
-                    Ok(md) => {
-                        data.root_music_dir = Some(md);
-                        load_random_tracks(TRACK_QUEUE_FILL_UNTIL, data);
-
-                        // update settings
-                        data.settings.root_music_path =
-                            path.into_os_string().into_string().unwrap();
-                        settings::write(&data.settings);
+                // update settings
+                data.settings.music_roots = roots;
+                settings::write(&data.data_dir, &data.settings);
 
-                        // Send play just to be sure
-                        data.player_req_sender
-                            .send(player_messages::Request::Play)
-                            .unwrap();
-                    }
-                    Err(e) => {
-                        data.root_music_dir = None;
-                        data.player_req_sender
-                            .send(player_messages::Request::Clear)
-                            .unwrap();
-                        data.event_sender
-                            .send(messages::Event::DirError(e))
-                            .unwrap();
-                    }
-                }
+                data.paused = false;
+                open_roots(data);
             }
             messages::Request::Play => {
                 println!("Backend Main: Play Sent");
+                data.paused = false;
                 data.player_req_sender
                     .send(player_messages::Request::Play)
                     .unwrap();
             }
             messages::Request::Pause => {
                 println!("Backend Main: pause Sent");
+                data.paused = true;
                 data.player_req_sender
                     .send(player_messages::Request::Pause)
                     .unwrap();
@@ -146,6 +388,10 @@ fn handle_request(res: Result<messages::Request, RecvError>, data: &mut ThreadDa
                     .unwrap();
             }
             messages::Request::Skip => {
+                if let Some(path) = data.current_track.clone() {
+                    data.track_stats.record_skip(&path);
+                    update_track_stat(&path, data);
+                }
                 data.player_req_sender
                     .send(player_messages::Request::Skip)
                     .unwrap();
@@ -157,11 +403,104 @@ fn handle_request(res: Result<messages::Request, RecvError>, data: &mut ThreadDa
                 // update settings
                 data.settings.volume = v;
                 // TODO: dont write every time the volume changes!
-                settings::write(&data.settings);
+                settings::write(&data.data_dir, &data.settings);
             }
             messages::Request::ProvideContext(c) => {
                 data.ctx = Some(c);
             }
+            messages::Request::SetRepeat(repeat) => {
+                data.settings.repeat = repeat;
+                settings::write(&data.data_dir, &data.settings);
+                data.player_req_sender
+                    .send(player_messages::Request::SetRepeatOne(
+                        repeat == Repeat::One,
+                    ))
+                    .unwrap();
+                // an order that ended may go on again
+                fill_queue(data);
+            }
+            messages::Request::SetPlayOrder(order) => {
+                // saved while the bag is still the current order
+                save_shuffle_state(data);
+                data.settings.play_order = order;
+                settings::write(&data.data_dir, &data.settings);
+                send_fades(data);
+                // the upcoming tracks were picked for the previous order, pick them again.
+                // The ones asked for by the user stay first.
+                data.last_picked = data.current_track.clone();
+                data.player_req_sender
+                    .send(player_messages::Request::EvictRandom)
+                    .unwrap();
+            }
+            messages::Request::RemoveFromQueue(i, path) => {
+                data.player_req_sender
+                    .send(player_messages::Request::Remove(i, path))
+                    .unwrap();
+            }
+            messages::Request::RerollInQueue(i, path) => {
+                data.reroll_slots.push(i);
+                data.player_req_sender
+                    .send(player_messages::Request::Remove(i, path))
+                    .unwrap();
+            }
+            messages::Request::MoveInQueue { from, to, path } => {
+                data.player_req_sender
+                    .send(player_messages::Request::Move { from, to, path })
+                    .unwrap();
+            }
+            messages::Request::PlayNow(path) => {
+                let tracks = get_tracks_at(&[path], data);
+                // the first one plays now, the others are put in front of the queue one by one
+                if let Some((first, others)) = tracks.split_first() {
+                    load_explicit(first.clone(), Placement::Now, data);
+                    for path in others.iter().rev() {
+                        load_explicit(path.clone(), Placement::Next, data);
+                    }
+                }
+            }
+            messages::Request::PlayNext(path) => {
+                for path in get_tracks_at(&[path], data).into_iter().rev() {
+                    load_explicit(path, Placement::Next, data);
+                }
+            }
+            messages::Request::Enqueue(paths) => {
+                for path in get_tracks_at(&paths, data) {
+                    load_explicit(path, Placement::AfterQueued, data);
+                }
+            }
+            messages::Request::Previous => {
+                play_previous(data);
+            }
+            messages::Request::SetRating(rating) => {
+                if let Some(path) = data.current_track.clone() {
+                    data.track_stats.set_rating(&path, rating);
+                    update_track_stat(&path, data);
+                }
+            }
+            messages::Request::SkipAlbum => {
+                skip_album(data);
+            }
+            messages::Request::FindDuplicates => {
+                find_duplicates(data);
+            }
+            messages::Request::SetPreferOneCopy(prefer) => {
+                data.settings.prefer_one_copy = prefer;
+                settings::write(&data.data_dir, &data.settings);
+                if prefer && data.duplicates.groups.is_empty() && data.duplicates_search.is_none() {
+                    find_duplicates(data);
+                }
+                refresh_music_dir(data);
+            }
+            messages::Request::ExplainExclusion(path) => {
+                let explanation = explain_exclusion(&path, data);
+                println!("[MAIN] {}: {explanation}", path.display());
+                data.event_sender
+                    .send(messages::Event::ExclusionExplained(path, explanation))
+                    .unwrap();
+                if let Some(c) = &data.ctx {
+                    c.request_repaint();
+                }
+            }
         },
         // TODO: handle this
         Err(e) => {
@@ -175,17 +514,71 @@ fn handle_load_response(res: Result<loader_messages::Response, RecvError>, data:
     match res {
         Ok(response) => {
             match response {
-                loader_messages::Response::Track(source, metadata) => {
+                loader_messages::Response::Track(path, source, metadata)
+                    if take_going_back_load(&path, data) =>
+                {
+                    data.player_req_sender
+                        .send(player_messages::Request::Insert(0, path, source, metadata))
+                        .unwrap();
+                    data.queued_tracks += 1;
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1);
+                    go_back_if_loaded(data);
+                }
+                loader_messages::Response::NotFound(path) if take_going_back_load(&path, data) => {
+                    println!("[MAIN] {path:?} not found, can't go back to it");
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1);
+                    update_library(vec![path], data);
+                    go_back_if_loaded(data);
+                }
+                loader_messages::Response::Track(path, source, metadata)
+                    if data.explicit_loads.first().is_some_and(|(p, _)| *p == path) =>
+                {
+                    let (_, placement) = data.explicit_loads.remove(0);
                     data.player_req_sender
-                        .send(player_messages::Request::Enqueue(source, metadata))
+                        .send(player_messages::Request::EnqueueExplicit(
+                            path, source, metadata, placement,
+                        ))
                         .unwrap();
                     data.queued_tracks += 1;
-                    data.loading_tracks -= 1
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1);
+                }
+                loader_messages::Response::NotFound(path)
+                    if data.explicit_loads.first().is_some_and(|(p, _)| *p == path) =>
+                {
+                    println!("[MAIN] {path:?} not found, can't play it");
+                    data.explicit_loads.remove(0);
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1);
+                    update_library(vec![path], data);
+                }
+                // a pick in the skipped album, the tracks asked for by the user were queued above
+                loader_messages::Response::Track(path, _, _)
+                    if data
+                        .skipped_album
+                        .as_ref()
+                        .is_some_and(|a| *a == sequential::get_album_folder(&path)) =>
+                {
+                    println!("[MAIN] {path:?} is in the skipped album, picking another track");
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1);
+                    fill_queue(data);
+                }
+                loader_messages::Response::Track(path, source, metadata) => {
+                    let request = match take_reroll_slot(&path, data) {
+                        Some(slot) => {
+                            player_messages::Request::Insert(slot, path, source, metadata)
+                        }
+                        None => player_messages::Request::Enqueue(path, source, metadata),
+                    };
+                    data.player_req_sender.send(request).unwrap();
+                    data.queued_tracks += 1;
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1)
                 }
-                // TODO: handle this
                 loader_messages::Response::NotFound(path) => {
-                    println!("{path:?} not found!!!!!");
-                    exit(1);
+                    // the file vanished after being picked: forget it and pick another one
+                    println!("[MAIN] {path:?} not found, picking another track");
+                    take_reroll_slot(&path, data);
+                    data.loading_tracks = data.loading_tracks.saturating_sub(1);
+                    update_library(vec![path], data);
+                    fill_queue(data);
                 }
             }
         }
@@ -201,13 +594,32 @@ fn handle_player_event(res: Result<player_messages::Event, RecvError>, data: &mu
         Ok(event) => {
             match event {
                 player_messages::Event::ProgressUpdate(d) => {
+                    data.progress = d;
                     data.event_sender
                         .send(messages::Event::ProgressUpdate(d))
                         .unwrap();
+                    if d.abs_diff(data.saved_progress) >= SESSION_SAVE_INTERVAL {
+                        save_session(data);
+                    }
                 }
-                player_messages::Event::NewTrackPlaying(metadata) => match metadata {
-                    None => {}
-                    Some(metadata) => {
+                player_messages::Event::NewTrackPlaying(track) => match track {
+                    None => {
+                        set_current_track(None, data);
+                        save_session(data);
+                    }
+                    Some((path, metadata)) => {
+                        let restoring = data.restoring.take();
+                        let restored_position = restoring
+                            .as_ref()
+                            .filter(|r| r.current.as_ref() == Some(&path))
+                            .map(|r| r.position);
+                        if restored_position.is_some() {
+                            // its play was counted in the last session
+                            update_track_stat(&path, data);
+                        } else {
+                            record_play(&path, data);
+                        }
+                        set_current_track(Some(path), data);
                         println!(
                             "[MAIN] Event::NewTrackPlaying received, name = {}. queued_tracks = {}",
                             metadata.name, data.queued_tracks
@@ -215,27 +627,57 @@ fn handle_player_event(res: Result<player_messages::Event, RecvError>, data: &mu
                         data.event_sender
                             .send(messages::Event::NewTrackPlaying(Some(metadata)))
                             .unwrap();
-                        let tracks_to_load = (TRACK_QUEUE_FILL_UNTIL as i16)
-                            - ((data.queued_tracks + data.loading_tracks) as i16);
-                        println!("[MAIN] Tracks to load: {tracks_to_load}");
-                        if tracks_to_load > 0 {
-                            load_random_tracks(tracks_to_load as u8, data);
+                        if restoring.is_some() {
+                            resume_session(restored_position, data);
                         }
+                        save_session(data);
                     }
                 },
+                // the counts saturate, as the player may tell about tracks queued before a clear
                 player_messages::Event::TrackFinished => {
-                    data.queued_tracks -= 1; // panics if underflow
+                    data.queued_tracks = data.queued_tracks.saturating_sub(1);
+                }
+                // the queue is filled again when the player tells it changed
+                player_messages::Event::TracksEvicted(n) => {
+                    data.queued_tracks = data.queued_tracks.saturating_sub(n);
+                }
+                player_messages::Event::TrackRemoved(slot) => {
+                    data.queued_tracks = data.queued_tracks.saturating_sub(1);
+                    if let Some(i) = data.reroll_slots.iter().position(|s| *s == slot) {
+                        // the new pick takes the place of the removed track
+                        data.reroll_slots.remove(i);
+                        let loading = data.loading_tracks;
+                        load_next_tracks(1, data);
+                        if data.loading_tracks > loading {
+                            let path = data.last_picked.clone().unwrap();
+                            data.rerolls.push((path, slot));
+                        }
+                    }
+                }
+                player_messages::Event::QueueChanged(upcoming) => {
+                    data.upcoming_durations = upcoming.iter().map(|(_, m, _)| m.duration).collect();
+                    data.upcoming = upcoming.iter().map(|(p, _, e)| (p.clone(), *e)).collect();
+                    save_session(data);
+                    let upcoming = upcoming.into_iter().map(|(p, m, _)| (p, m)).collect();
+                    data.event_sender
+                        .send(messages::Event::QueueChanged(upcoming))
+                        .unwrap();
+                    fill_queue(data);
                 }
                 player_messages::Event::JumpedTo(d) => {
+                    data.progress = d;
                     data.event_sender
                         .send(messages::Event::JumpedTo(d))
                         .unwrap();
+                    save_session(data);
                 }
                 player_messages::Event::NowPlaying => {
                     data.event_sender.send(messages::Event::NowPlaying).unwrap();
+                    save_session(data);
                 }
                 player_messages::Event::NowPaused => {
                     data.event_sender.send(messages::Event::NowPaused).unwrap();
+                    save_session(data);
                 }
             }
             if let Some(c) = &data.ctx {
@@ -249,16 +691,873 @@ fn handle_player_event(res: Result<player_messages::Event, RecvError>, data: &mu
     }
 }
 
-fn load_random_tracks(amount: u8, data: &mut ThreadData) {
+fn handle_watcher_event(res: Result<watcher_messages::Event, RecvError>, data: &mut ThreadData) {
+    match res {
+        Ok(event) => match event {
+            watcher_messages::Event::Changed(paths) => {
+                update_library(paths, data);
+            }
+        },
+        Err(e) => {
+            println!("Error in handle watcher event: {e:?}");
+            exit(1);
+        }
+    }
+}
+
+fn handle_scan_event(res: Result<scanner_messages::Event, RecvError>, data: &mut ThreadData) {
+    let event = match res {
+        Ok(event) => event,
+        Err(e) => {
+            println!("Error in handle scan event: {e:?}");
+            exit(1);
+        }
+    };
+    let running_id = data.scan.as_ref().map(|s| s.id);
+
+    match event {
+        // events of a cancelled scan are dropped
+        scanner_messages::Event::Progress { id, .. }
+        | scanner_messages::Event::Found(id, _)
+        | scanner_messages::Event::Finished { id, .. }
+            if Some(id) != running_id => {}
+        scanner_messages::Event::Progress {
+            dirs,
+            files,
+            current_path,
+            ..
+        } => {
+            data.event_sender
+                .send(messages::Event::ScanProgress {
+                    dirs,
+                    files,
+                    current_path,
+                })
+                .unwrap();
+        }
+        scanner_messages::Event::Found(_, entries) => {
+            println!("[MAIN] scan found {} new tracks", entries.len());
+            for entry in entries {
+                data.library_index.insert(entry);
+            }
+            if data.music_dir.is_some() {
+                data.music_dir = build_music_dir(data).0.or(data.music_dir.take());
+            } else {
+                play_if_idle(data);
+            }
+        }
+        scanner_messages::Event::Finished { index, results, .. } => {
+            finish_scan(index, results, data);
+        }
+    }
+    if let Some(c) = &data.ctx {
+        c.request_repaint();
+    }
+}
+
+/// Takes the index of the finished scan and rebuilds the music dir from it.
+fn finish_scan(
+    index: LibraryIndex,
+    results: Vec<(PathBuf, Result<ScanReport, MusicDirCreationError>)>,
+    data: &mut ThreadData,
+) {
+    let Some(scan) = data.scan.take() else {
+        return;
+    };
+    // the scan worked on a copy made before the last hashes and fingerprints came in
+    let computed = mem::replace(&mut data.library_index, index);
+    data.library_index.keep_computed(&computed);
+    save_index(data);
+
+    let mut report = ScanReport::default();
+    let mut errors = vec![];
+    for (root, res) in results {
+        match res {
+            Ok(r) => report.extend(r),
+            Err(e) => {
+                println!("[MAIN] error in loading {}: {e}", root.display());
+                errors.push((root, e));
+            }
+        }
+    }
+    let (music_dir, build_errors) = build_music_dir(data);
+    // a root that failed to scan is empty too, its scan error tells more
+    for (root, e) in build_errors {
+        if !errors.iter().any(|(r, _)| *r == root) {
+            errors.push((root, e));
+        }
+    }
+    let first_error = errors.first().map(|(_, e)| e.clone());
+    let tracks = music_dir.as_ref().map_or(0, |md| md.get_track_count());
+    println!(
+        "[MAIN] scan finished: {tracks} tracks, {} entries skipped, {} folders failed",
+        report.problems.len(),
+        errors.len()
+    );
+    data.event_sender
+        .send(messages::Event::ScanFinished { tracks, report })
+        .unwrap();
+    data.event_sender
+        .send(messages::Event::RootErrors(errors))
+        .unwrap();
+
+    match music_dir {
+        Some(md) => {
+            let was_idle = data.music_dir.is_none();
+            data.music_dir = Some(md);
+            if was_idle {
+                fill_queue(data);
+                // a paused session being restored stays paused
+                if !data.paused {
+                    data.player_req_sender
+                        .send(player_messages::Request::Play)
+                        .unwrap();
+                }
+            }
+        }
+        None => {
+            data.music_dir = None;
+            data.player_req_sender
+                .send(player_messages::Request::Clear)
+                .unwrap();
+            data.queued_tracks = 0;
+            data.event_sender
+                .send(messages::Event::DirError(
+                    first_error.unwrap_or(MusicDirCreationError::Empty),
+                ))
+                .unwrap();
+        }
+    }
+
+    if !scan.pending_changes.is_empty() {
+        update_library(scan.pending_changes, data);
+    }
+    if data.settings.prefer_one_copy {
+        find_duplicates(data);
+    }
+}
+
+fn handle_duplicates_event(
+    res: Result<duplicates_messages::Event, RecvError>,
+    data: &mut ThreadData,
+) {
+    let running_id = data.duplicates_search.as_ref().map(|s| s.id);
+    match res {
+        Ok(event) => match event {
+            // events of a cancelled search are dropped, what it computed is still cached
+            duplicates_messages::Event::Progress { id, .. }
+            | duplicates_messages::Event::Found(id, _)
+                if Some(id) != running_id => {}
+            duplicates_messages::Event::Progress { done, total, .. } => {
+                data.event_sender
+                    .send(messages::Event::DuplicatesProgress { done, total })
+                    .unwrap();
+            }
+            duplicates_messages::Event::Computed(entries) => {
+                for entry in entries {
+                    data.library_index.update_if_unchanged(entry);
+                }
+                if data.index_saved_at.elapsed() >= INDEX_SAVE_INTERVAL {
+                    save_index(data);
+                }
+            }
+            duplicates_messages::Event::Found(_, report) => {
+                data.duplicates_search = None;
+                save_index(data);
+                data.duplicates = report.clone();
+                data.event_sender
+                    .send(messages::Event::DuplicatesFound(report))
+                    .unwrap();
+                if data.settings.prefer_one_copy {
+                    refresh_music_dir(data);
+                }
+            }
+        },
+        Err(e) => {
+            println!("Error in handle duplicates event: {e:?}");
+            exit(1);
+        }
+    }
+    if let Some(c) = &data.ctx {
+        c.request_repaint();
+    }
+}
+
+/// Compares the playable tracks in the duplicates thread, cancelling the running search.
+fn find_duplicates(data: &mut ThreadData) {
+    if let Some(search) = data.duplicates_search.take() {
+        println!("[MAIN] cancelling duplicates search {}", search.id);
+        search.cancel.store(true, Ordering::Relaxed);
+    }
+    let roots = get_scan_roots(&data.settings);
+    let tracks = data
+        .library_index
+        .get_tracks_in(&roots)
+        .into_iter()
+        .filter(|e| {
+            e.format
+                .is_some_and(|f| data.settings.enabled_formats.contains(&f))
+        })
+        .filter(|e| data.scan_rules.check_duration(e.duration).is_none())
+        .cloned()
+        .collect();
+    data.next_search_id += 1;
+    let cancel = Arc::new(AtomicBool::new(false));
+    data.duplicates_req_sender
+        .send(duplicates_messages::Request::Find {
+            id: data.next_search_id,
+            entries: tracks,
+            cancel: cancel.clone(),
+        })
+        .unwrap();
+    data.duplicates_search = Some(RunningSearch {
+        id: data.next_search_id,
+        cancel,
+    });
+}
+
+/// Rebuilds the music dir if one is playing, e.g. after the tracks to skip changed.
+fn refresh_music_dir(data: &mut ThreadData) {
+    if data.music_dir.is_none() {
+        return;
+    }
+    if let Some(md) = build_music_dir(data).0 {
+        data.music_dir = Some(md);
+    }
+}
+
+/// Updates the index and the music dir after files changed on disk,
+/// and evicts the queued tracks whose files vanished.
+/// During a scan the changes wait for its end, as the scan replaces the index.
+fn update_library(paths: Vec<PathBuf>, data: &mut ThreadData) {
+    let vanished: Vec<PathBuf> = paths.iter().filter(|p| !p.exists()).cloned().collect();
+    if !vanished.is_empty() {
+        data.player_req_sender
+            .send(player_messages::Request::Evict(vanished))
+            .unwrap();
+    }
+    if let Some(scan) = &mut data.scan {
+        scan.pending_changes.extend(paths);
+        return;
+    }
+
+    let roots = data.settings.get_enabled_roots();
+    let (added, removed) = data
+        .library_index
+        .update_paths(&paths, &roots, &data.scan_rules);
+    // saved once the changes settle, a copy or an extraction comes in many batches
+    data.index_changed_at = Some(Instant::now());
+    println!("[MAIN] library updated: {added} tracks added, {removed} removed");
+
+    if data.music_dir.is_some() {
+        match build_music_dir(data).0 {
+            Some(md) => data.music_dir = Some(md),
+            None => {
+                // every track is gone
+                data.music_dir = None;
+                data.player_req_sender
+                    .send(player_messages::Request::Clear)
+                    .unwrap();
+                data.queued_tracks = 0;
+                data.event_sender
+                    .send(messages::Event::DirError(MusicDirCreationError::Empty))
+                    .unwrap();
+            }
+        }
+    }
+
+    if added > 0 || removed > 0 {
+        data.event_sender
+            .send(messages::Event::LibraryChanged { added, removed })
+            .unwrap();
+        if let Some(c) = &data.ctx {
+            c.request_repaint();
+        }
+    }
+}
+
+fn save_index(data: &mut ThreadData) {
+    library_index::write(&data.data_dir, &data.library_index);
+    data.index_saved_at = Instant::now();
+    data.index_changed_at = None;
+}
+
+/// Saves the index once its changes settled, and the picks made a while ago.
+fn save_pending_changes(data: &mut ThreadData) {
+    if data
+        .index_changed_at
+        .is_some_and(|t| t.elapsed() >= INDEX_WRITE_DELAY)
+    {
+        save_index(data);
+    }
+    if data
+        .shuffle_changed_at
+        .is_some_and(|t| t.elapsed() >= SHUFFLE_SAVE_INTERVAL)
+    {
+        save_shuffle_state(data);
+    }
+}
+
+/// Starts rescanning the enabled roots in the scanner thread, cancelling the running scan.
+fn start_scan(data: &mut ThreadData) {
+    let mut pending_changes = vec![];
+    if let Some(scan) = data.scan.take() {
+        println!("[MAIN] cancelling scan {}", scan.id);
+        scan.cancel.store(true, Ordering::Relaxed);
+        pending_changes = scan.pending_changes;
+    }
+
+    let roots = get_scan_roots(&data.settings);
+    if roots.is_empty() {
+        data.event_sender
+            .send(messages::Event::DirError(
+                MusicDirCreationError::NoRootEnabled,
+            ))
+            .unwrap();
+        if !pending_changes.is_empty() {
+            update_library(pending_changes, data);
+        }
+        return;
+    }
+
+    data.next_scan_id += 1;
+    let cancel = Arc::new(AtomicBool::new(false));
+    data.scan_req_sender
+        .send(scanner_messages::Request::Scan {
+            id: data.next_scan_id,
+            roots,
+            index: data.library_index.clone(),
+            rules: data.scan_rules.clone(),
+            cancel: cancel.clone(),
+        })
+        .unwrap();
+    data.scan = Some(RunningScan {
+        id: data.next_scan_id,
+        cancel,
+        pending_changes,
+    });
+}
+
+/// Starts playing from the indexed tracks if nothing is playing yet.
+fn play_if_idle(data: &mut ThreadData) {
+    if data.music_dir.is_some() {
+        return;
+    }
+    if let Some(md) = build_music_dir(data).0 {
+        data.music_dir = Some(md);
+        fill_queue(data);
+
+        // Send play just to be sure
+        if !data.paused {
+            data.player_req_sender
+                .send(player_messages::Request::Play)
+                .unwrap();
+        }
+    }
+}
+
+/// Watches and scans the enabled roots, the tracks already indexed can play meanwhile.
+fn open_roots(data: &mut ThreadData) {
+    // keep the music dir in sync with the disk
+    data.watcher_req_sender
+        .send(watcher_messages::Request::Watch(
+            data.settings.get_enabled_roots(),
+            data.scan_rules.clone(),
+        ))
+        .unwrap();
+
+    // update the index in the background
+    data.music_dir = None;
+    start_scan(data);
+    if data.scan.is_some() {
+        play_if_idle(data);
+    }
+}
+
+/// Loads the tracks playing and queued when the app was closed, and the library as if
+/// its folders were chosen again.
+fn restore_session(session: Session, data: &mut ThreadData) {
+    if session.is_empty() {
+        return;
+    }
+    println!("[MAIN] Restoring the last session");
+    data.event_sender
+        .send(messages::Event::RestoringSession)
+        .unwrap();
+    data.paused = session.paused;
+    if data.paused {
+        // before anything is loaded, so that none of it is heard
+        data.player_req_sender
+            .send(player_messages::Request::Pause)
+            .unwrap();
+    }
+    data.restoring = Some(Restoring {
+        current: session.current.clone(),
+        position: session.position,
+    });
+    // requested before the scan starts, so the new picks are loaded after them.
+    // The tracks picked for the play order are restored as picks, so they can still be
+    // evicted and picked again.
+    let explicit = session.explicit.into_iter().chain(iter::repeat(false));
+    let restored = session
+        .current
+        .map(|path| (path, false))
+        .into_iter()
+        .chain(session.upcoming.into_iter().zip(explicit))
+        .take(MAX_QUEUE_LEN);
+    for (path, explicit) in restored {
+        if explicit {
+            load_explicit(path, Placement::AfterQueued, data);
+        } else {
+            load_pick(path, data);
+        }
+    }
+    open_roots(data);
+}
+
+/// Seeks the restored track to where it was, and pauses it if the session was paused,
+/// now that the frontend shows it.
+fn resume_session(position: Option<Duration>, data: &mut ThreadData) {
+    if let Some(position) = position.filter(|p| !p.is_zero()) {
+        data.player_req_sender
+            .send(player_messages::Request::JumpTo(position))
+            .unwrap();
+    }
+    if data.paused {
+        data.player_req_sender
+            .send(player_messages::Request::Pause)
+            .unwrap();
+    }
+}
+
+/// Saves what is playing, unless the last session is still being restored or replayed.
+fn save_session(data: &mut ThreadData) {
+    if data.restoring.is_some() || data.replayed_picks.is_some() {
+        return;
+    }
+    data.saved_progress = data.progress;
+    session::write(
+        &data.data_dir,
+        &Session {
+            current: data.current_track.clone(),
+            position: data.progress,
+            paused: data.paused,
+            upcoming: data.upcoming.iter().map(|(p, _)| p.clone()).collect(),
+            explicit: data.upcoming.iter().map(|(_, e)| *e).collect(),
+        },
+    );
+}
+
+/// Enabled roots, without the ones inside another enabled root as they are already part of it.
+fn get_scan_roots(settings: &Settings) -> Vec<PathBuf> {
+    let roots = settings.get_enabled_roots();
+    roots
+        .iter()
+        .filter(|&root| {
+            let nested = roots.iter().any(|r| r != root && root.starts_with(r));
+            if nested {
+                println!(
+                    "[MAIN] {} is inside another folder, skipped",
+                    root.display()
+                );
+            }
+            !nested
+        })
+        .cloned()
+        .collect()
+}
+
+/// Builds the music dir from the indexed tracks of the enabled roots.
+/// Also returns the roots that have no playable track, the others may still play.
+fn build_music_dir(data: &ThreadData) -> (Option<MusicDir>, Vec<(PathBuf, MusicDirCreationError)>) {
+    let skipped = if data.settings.prefer_one_copy {
+        data.duplicates.get_non_preferred().collect()
+    } else {
+        HashSet::new()
+    };
+    let mut music_dirs = vec![];
+    let mut errors = vec![];
+    for root in get_scan_roots(&data.settings) {
+        match MusicDir::new(
+            &root,
+            &data.library_index,
+            &data.settings.enabled_formats,
+            &data.scan_rules,
+            &skipped,
+        ) {
+            Ok(md) => music_dirs.push(md),
+            Err(e) => errors.push((root, e)),
+        }
+    }
+
+    if music_dirs.is_empty() {
+        (None, errors)
+    } else {
+        (Some(MusicDir::merge(music_dirs)), errors)
+    }
+}
+
+/// Tells why `path` is or isn't part of the library.
+fn explain_exclusion(path: &Path, data: &ThreadData) -> String {
+    let roots = data.settings.get_enabled_roots();
+    let Some(root) = roots.iter().find(|r| path.starts_with(r)) else {
+        return "not inside an enabled music folder".to_string();
+    };
+    if let Some(exclusion) = data.scan_rules.check_from_root(root, path, path.is_dir()) {
+        return format!("excluded: {exclusion}");
+    }
+    if path.is_dir() {
+        return "folder not excluded".to_string();
+    }
+    let Some(entry) = data.library_index.get(path) else {
+        return "not indexed yet (press 🔀 to rescan)".to_string();
+    };
+    let Some(format) = entry.format else {
+        return "not a playable audio file".to_string();
+    };
+    if !data.settings.enabled_formats.contains(&format) {
+        return format!("format {format:?} is disabled in settings");
+    }
+    if let Some(exclusion) = data.scan_rules.check_duration(entry.duration) {
+        return format!("excluded: {exclusion}");
+    }
+    if data.settings.prefer_one_copy {
+        if let Some(preferred) = data.duplicates.get_preferred_copy(path) {
+            return format!(
+                "duplicate of {}, which is played instead",
+                preferred.display()
+            );
+        }
+    }
+    "included".to_string()
+}
+
+/// Tracks loaded again to go back, prepended one after the other to the player queue.
+struct GoingBack {
+    pending: Vec<PathBuf>, // loads still expected, in order
+}
+
+/// Keeps the track that stopped playing in the history, unless the player is going back.
+fn set_current_track(track: Option<PathBuf>, data: &mut ThreadData) {
+    let going_back = data
+        .going_back
+        .as_ref()
+        .is_some_and(|g| g.pending.is_empty());
+    if going_back {
+        data.going_back = None;
+    } else if let Some(previous) = data.current_track.take() {
+        data.history.push(previous);
+        if data.history.len() > HISTORY_LEN {
+            data.history.remove(0);
+        }
+    }
+    data.current_track = track;
+    data.progress = Duration::ZERO;
+}
+
+/// Restarts the current track, or plays the previous one if the current one just started.
+fn play_previous(data: &mut ThreadData) {
+    if data.going_back.is_some() {
+        return;
+    }
+    let Some(current) = data.current_track.clone() else {
+        return;
+    };
+    let Some(previous) = data.history.pop_if(|_| data.progress <= RESTART_THRESHOLD) else {
+        data.player_req_sender
+            .send(player_messages::Request::Restart)
+            .unwrap();
+        return;
+    };
+    println!("[MAIN] Going back to {}", previous.display());
+    // prepended in this order, the previous track comes first, then the current one again
+    let pending = vec![current, previous];
+    for path in &pending {
+        data.load_req_sender
+            .send(loader_messages::Request::Track(path.clone()))
+            .unwrap();
+    }
+    data.loading_tracks += pending.len();
+    data.going_back = Some(GoingBack { pending });
+}
+
+/// Whether `path` is the next track loaded to go back, which is then no longer expected.
+fn take_going_back_load(path: &Path, data: &mut ThreadData) -> bool {
+    let Some(going_back) = &mut data.going_back else {
+        return false;
+    };
+    if going_back.pending.first().is_none_or(|p| p != path) {
+        return false;
+    }
+    going_back.pending.remove(0);
+    true
+}
+
+/// Skips the current track once both tracks are prepended, so the previous one plays.
+fn go_back_if_loaded(data: &mut ThreadData) {
+    if data
+        .going_back
+        .as_ref()
+        .is_some_and(|g| g.pending.is_empty())
+    {
+        data.player_req_sender
+            .send(player_messages::Request::Skip)
+            .unwrap();
+    }
+}
+
+/// The library tracks at these paths, in library order for folders, as many as the queue takes.
+/// Files left out of the library are ignored, as they may not be playable.
+fn get_tracks_at(paths: &[PathBuf], data: &ThreadData) -> Vec<PathBuf> {
+    let Some(music_dir) = &data.music_dir else {
+        return vec![];
+    };
+    let order = sequential::get_tree_order(music_dir, &data.library_index);
+    let mut tracks = vec![];
+    for path in paths {
+        let before = tracks.len();
+        tracks.extend(
+            order
+                .iter()
+                .filter(|p| p.starts_with(path))
+                .map(|p| (*p).clone()),
+        );
+        if tracks.len() == before {
+            println!("[MAIN] No track of the library at {}", path.display());
+        }
+    }
+    let queued = data.queued_tracks + data.loading_tracks;
+    let room = MAX_QUEUE_LEN.saturating_sub(queued);
+    if tracks.len() > room {
+        println!(
+            "[MAIN] Queue full, {} tracks not queued",
+            tracks.len() - room
+        );
+        tracks.truncate(room);
+    }
+    tracks
+}
+
+fn load_explicit(path: PathBuf, placement: Placement, data: &mut ThreadData) {
+    println!(
+        "[MAIN] Sending explicit load request, path = {}",
+        path.display()
+    );
+    data.load_req_sender
+        .send(loader_messages::Request::Track(path.clone()))
+        .unwrap();
+    data.explicit_loads.push((path, placement));
+    data.loading_tracks += 1;
+}
+
+/// Loads a track picked for the play order earlier, the sequential orders go on from it.
+fn load_pick(path: PathBuf, data: &mut ThreadData) {
+    data.load_req_sender
+        .send(loader_messages::Request::Track(path.clone()))
+        .unwrap();
+    data.last_picked = Some(path);
+    data.loading_tracks += 1;
+}
+
+/// The place in the queue of a track picked to replace another one, if it is such a track.
+fn take_reroll_slot(path: &Path, data: &mut ThreadData) -> Option<usize> {
+    let i = data.rerolls.iter().position(|(p, _)| p == path)?;
+    Some(data.rerolls.remove(i).1)
+}
+
+/// Drops the rest of the album of the current track, the tracks go on with the next album.
+/// The tracks of the album asked for by the user stay queued.
+fn skip_album(data: &mut ThreadData) {
+    let (Some(music_dir), Some(current)) = (&data.music_dir, &data.current_track) else {
+        return;
+    };
+    let folder = sequential::get_album_folder(current);
+    println!("[MAIN] Skipping album {}", folder.display());
+    data.last_picked = match data.settings.play_order {
+        // a random album follows, even when repeating albums
+        PlayOrder::RandomAlbum => None,
+        // the next album starts after the last track of this one
+        _ => sequential::get_last_in_album(music_dir, &data.library_index, current),
+    };
+    let album_tracks = music_dir
+        .get_albums()
+        .into_iter()
+        .find(|a| a.contains(current))
+        .map(<[PathBuf]>::to_vec)
+        .unwrap_or_default();
+    data.skipped_album = Some(folder);
+    data.player_req_sender
+        .send(player_messages::Request::EvictRandomAt(album_tracks))
+        .unwrap();
+    data.player_req_sender
+        .send(player_messages::Request::Skip)
+        .unwrap();
+}
+
+fn record_play(path: &Path, data: &mut ThreadData) {
+    data.track_stats.record_play(path, SystemTime::now());
+    update_track_stat(path, data);
+}
+
+/// Saves the statistics after a change to the track, and tells its new rating and weight.
+fn update_track_stat(path: &Path, data: &mut ThreadData) {
+    if data.replayed_picks.is_none() {
+        track_stats::write(&data.data_dir, &data.track_stats);
+    }
+    if let Some(md) = &data.music_dir {
+        let weight =
+            data.track_stats
+                .get_weight(path, &data.settings.weight_curve, SystemTime::now());
+        md.update_weight(path, weight);
+    }
+    let rating = data.track_stats.get(path).and_then(|s| s.rating);
+    data.event_sender
+        .send(messages::Event::CurrentRating(rating))
+        .unwrap();
+}
+
+fn get_artist<'a>(path: &Path, index: &'a LibraryIndex) -> Option<&'a str> {
+    index.get(path)?.artist.as_deref()
+}
+
+/// Loads the next track while the upcoming ones play for less than `prefetch_secs`,
+/// and are fewer than `max_prefetch_tracks`. One at a time, as the duration of a track
+/// is only known once it is loaded.
+fn fill_queue(data: &mut ThreadData) {
+    if data.loading_tracks > 0 {
+        return;
+    }
+    let count = data.upcoming_durations.len();
+    let buffered: Duration = data
+        .upcoming_durations
+        .iter()
+        .map(|d| d.unwrap_or(UNKNOWN_TRACK_DURATION))
+        .sum();
+    let prefetch = Duration::from_secs(data.settings.prefetch_secs.into());
+    if count >= usize::from(data.settings.max_prefetch_tracks.max(1))
+        || (count > 0 && buffered >= prefetch)
+    {
+        return;
+    }
+    println!("[MAIN] {count} upcoming tracks, {buffered:?} buffered, loading one more");
+    load_next_tracks(1, data);
+}
+
+fn load_next_tracks(amount: usize, data: &mut ThreadData) {
+    let Some(music_dir) = &data.music_dir else {
+        println!("[MAIN] No music dir, no tracks to load");
+        return;
+    };
     println!("[MAIN] Will send {amount} loading requests");
+    let window_len = data
+        .settings
+        .no_repeat_window
+        .get_len(music_dir.get_track_count());
+    let mut avoided = data.shuffle_history.get_window(window_len);
+    let album_window_len = data
+        .settings
+        .album_no_repeat_window
+        .get_len(music_dir.get_album_count());
+    if data.settings.play_order == PlayOrder::ShuffleBag {
+        data.shuffle_bag
+            .sync(&music_dir.get_all_track_paths(), &mut data.rng);
+    }
+    let wrap = data.settings.repeat == Repeat::All;
+    let mut spacing = Spacing::new(data.settings.shuffle_spacing);
+    for path in data.shuffle_history.get_recent(spacing.get_len()) {
+        spacing.push(
+            get_artist(path, &data.library_index),
+            &sequential::get_album_folder(path),
+        );
+    }
+    let history_len = window_len.max(spacing.get_len());
+    let mut sent = 0;
     for _ in 0..amount {
         // println!("Loading {i} / {amount}");
-        let random_path = data
-            .root_music_dir
+        let replayed = data.replayed_picks.as_mut().and_then(|p| p.pop_front());
+        let picked = replayed.or_else(|| match data.settings.play_order {
+            PlayOrder::Random => spacing.pick(
+                music_dir,
+                data.settings.shuffle_weighting,
+                &mut data.rng,
+                &avoided,
+                |path| get_artist(path, &data.library_index),
+            ),
+            PlayOrder::ShuffleBag => data.shuffle_bag.next(&mut data.rng),
+            PlayOrder::Weighted => {
+                let now = SystemTime::now();
+                music_dir.get_weighted_random_track_path(
+                    |path| {
+                        data.track_stats
+                            .get_weight(path, &data.settings.weight_curve, now)
+                    },
+                    &mut data.rng,
+                    &avoided,
+                )
+            }
+            PlayOrder::SequentialFolder => sequential::get_next_in_folder(
+                music_dir,
+                &data.library_index,
+                data.last_picked.as_deref(),
+                wrap,
+            ),
+            PlayOrder::SequentialTree => sequential::get_next_in_tree(
+                music_dir,
+                &data.library_index,
+                data.last_picked.as_deref(),
+                wrap,
+            ),
+            PlayOrder::RandomAlbum => data
+                .last_picked
+                .as_deref()
+                .and_then(|last| {
+                    if wrap {
+                        // the album over and over
+                        sequential::get_next_in_folder(
+                            music_dir,
+                            &data.library_index,
+                            Some(last),
+                            true,
+                        )
+                    } else {
+                        sequential::get_next_in_album(music_dir, &data.library_index, last)
+                    }
+                })
+                .or_else(|| {
+                    let avoided_albums = data.shuffle_history.get_album_window(album_window_len);
+                    let first = sequential::get_random_album_start(
+                        music_dir,
+                        &data.library_index,
+                        &avoided_albums,
+                        &mut data.rng,
+                    )?;
+                    let folder = sequential::get_album_folder(&first);
+                    println!("[MAIN] Next album: {}", folder.display());
+                    data.shuffle_history.push_album(folder, album_window_len);
+                    Some(first)
+                }),
+        });
+        let Some(random_path) = picked else {
+            println!("[MAIN] End of the play order reached");
+            break;
+        };
+        if data
+            .skipped_album
             .as_ref()
-            .expect("Error: no music dir")
-            .get_random_track_path()
-            .unwrap();
+            .is_some_and(|a| *a == sequential::get_album_folder(&random_path))
+        {
+            data.skipped_album = None; // picked again on purpose
+        }
+        data.last_picked = Some(random_path.clone());
+        spacing.push(
+            get_artist(&random_path, &data.library_index),
+            &sequential::get_album_folder(&random_path),
+        );
+        data.shuffle_history.push(random_path.clone(), history_len);
+        avoided.insert(random_path.clone());
+        if data.replayed_picks.is_none() {
+            replay::record_pick(&data.data_dir, &random_path);
+        }
         println!(
             "[MAIN] Sending load request, path = {}",
             random_path.display()
@@ -266,10 +1565,574 @@ fn load_random_tracks(amount: u8, data: &mut ThreadData) {
         data.load_req_sender
             .send(loader_messages::Request::Track(random_path))
             .unwrap();
+        sent += 1;
     }
-    data.loading_tracks += amount;
+    if sent > 0 {
+        data.shuffle_changed_at.get_or_insert_with(Instant::now);
+    }
+    data.loading_tracks += sent;
     println!(
-        "[MAIN] {amount} loading requests sent, loading_tracks = {}",
+        "[MAIN] {sent} loading requests sent, loading_tracks = {}",
         data.loading_tracks
     );
 }
+
+/// Saves the shuffle history and bag, unless a session is being replayed.
+fn save_shuffle_state(data: &mut ThreadData) {
+    data.shuffle_changed_at = None;
+    if data.replayed_picks.is_some() {
+        return;
+    }
+    shuffle_history::write(&data.data_dir, &data.shuffle_history);
+    if data.settings.play_order == PlayOrder::ShuffleBag {
+        shuffle_bag::write(&data.data_dir, &data.shuffle_bag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::{env, fs, process};
+
+    use crate::audio_format::AudioFormat;
+    use crate::backend::library_index::IndexEntry;
+    use crate::backend::track_source::{self, TrackSource};
+    use crate::settings::MusicRoot;
+
+    // the receiving ends of the other threads, kept open for the sends to succeed
+    struct Threads {
+        load: Receiver<loader_messages::Request>,
+        _events: Receiver<messages::Event>,
+        player: Receiver<player_messages::Request>,
+        _watcher: Receiver<watcher_messages::Request>,
+        _scanner: Receiver<scanner_messages::Request>,
+        _duplicates: Receiver<duplicates_messages::Request>,
+    }
+
+    // the picks save the shuffle history and bag, each test in its own folder
+    fn make_data_dir() -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("rustify-main-loop-tests-{}/{n}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 3 artists with 4 albums of 10 tracks each
+    fn make_library() -> (LibraryIndex, MusicDir) {
+        let mut index = LibraryIndex::default();
+        let mut paths = vec![];
+        for artist in 0..3 {
+            for album in 0..4 {
+                for track in 0..10 {
+                    let path = PathBuf::from(format!(
+                        "/music/artist {artist}/album {album}/{track:02}.mp3"
+                    ));
+                    index.insert(IndexEntry {
+                        path: path.clone(),
+                        modified: SystemTime::UNIX_EPOCH,
+                        size: 0,
+                        format: Some(AudioFormat::Mp3),
+                        name: None,
+                        artist: Some(format!("Artist {artist}")),
+                        album: None,
+                        track_number: Some(track + 1),
+                        disc_number: None,
+                        duration: Some(Duration::from_secs(200)),
+                        content_hash: None,
+                        fingerprint: None,
+                    });
+                    paths.push(path);
+                }
+            }
+        }
+        let music_dir = MusicDir::from_track_paths(Path::new("/music"), paths).unwrap();
+        (index, music_dir)
+    }
+
+    fn make_state() -> ShuffleState {
+        ShuffleState {
+            history: ShuffleHistory::default(),
+            bag: ShuffleBag::default(),
+            stats: TrackStats::default(),
+        }
+    }
+
+    fn make_data(order: PlayOrder, state: ShuffleState, seed: u64) -> (ThreadData, Threads) {
+        make_data_in(make_data_dir(), order, state, seed)
+    }
+
+    fn make_data_in(
+        data_dir: PathBuf,
+        order: PlayOrder,
+        state: ShuffleState,
+        seed: u64,
+    ) -> (ThreadData, Threads) {
+        let (library_index, music_dir) = make_library();
+        let mut settings = Settings::default();
+        settings.play_order = order;
+        let (event_sender, events) = unbounded();
+        let (player_req_sender, player) = unbounded();
+        let (load_req_sender, load) = unbounded();
+        let (watcher_req_sender, watcher) = unbounded();
+        let (scan_req_sender, scanner) = unbounded();
+        let (duplicates_req_sender, duplicates) = unbounded();
+        let mut data = ThreadData::new(
+            data_dir,
+            settings,
+            library_index,
+            state,
+            StdRng::seed_from_u64(seed),
+            event_sender,
+            player_req_sender,
+            load_req_sender,
+            watcher_req_sender,
+            scan_req_sender,
+            duplicates_req_sender,
+        );
+        data.music_dir = Some(music_dir);
+        let threads = Threads {
+            load,
+            _events: events,
+            player,
+            _watcher: watcher,
+            _scanner: scanner,
+            _duplicates: duplicates,
+        };
+        (data, threads)
+    }
+
+    fn take_loads(threads: &Threads) -> Vec<PathBuf> {
+        threads
+            .load
+            .try_iter()
+            .map(|req| match req {
+                loader_messages::Request::Track(path) => path,
+            })
+            .collect()
+    }
+
+    // `n` picks, loaded one after another
+    fn pick(n: usize, data: &mut ThreadData, threads: &Threads) -> Vec<PathBuf> {
+        let mut picks = vec![];
+        for _ in 0..n {
+            load_next_tracks(1, data);
+            data.loading_tracks = 0;
+            picks.extend(take_loads(threads));
+        }
+        picks
+    }
+
+    // the loader answers every request with a track of this duration
+    fn answer_loads(duration: Option<Duration>, data: &mut ThreadData, threads: &Threads) -> usize {
+        let loads = take_loads(threads);
+        for _ in &loads {
+            data.loading_tracks -= 1;
+            data.upcoming_durations.push(duration);
+        }
+        loads.len()
+    }
+
+    // fills the queue like the loop does: one more load once the last one is answered
+    fn fill(duration: Option<Duration>, data: &mut ThreadData, threads: &Threads) -> usize {
+        let mut loaded = 0;
+        loop {
+            fill_queue(data);
+            match answer_loads(duration, data, threads) {
+                0 => return loaded,
+                n => loaded += n,
+            }
+        }
+    }
+
+    #[test]
+    fn fill_queue_loads_until_the_prefetch_is_reached() {
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 1);
+        data.settings.prefetch_secs = 300;
+        data.settings.max_prefetch_tracks = 10;
+
+        // one at a time: nothing more is asked for while a load is pending
+        fill_queue(&mut data);
+        fill_queue(&mut data);
+        assert_eq!(data.loading_tracks, 1);
+        assert_eq!(
+            answer_loads(Some(Duration::from_secs(60)), &mut data, &threads),
+            1
+        );
+
+        assert_eq!(fill(Some(Duration::from_secs(60)), &mut data, &threads), 4);
+        assert_eq!(data.upcoming_durations.len(), 5);
+
+        // a track finished, one more makes up for it
+        data.upcoming_durations.remove(0);
+        assert_eq!(fill(Some(Duration::from_secs(60)), &mut data, &threads), 1);
+    }
+
+    #[test]
+    fn fill_queue_stops_at_max_prefetch_tracks() {
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 1);
+        data.settings.prefetch_secs = 3600;
+        data.settings.max_prefetch_tracks = 3;
+        assert_eq!(fill(Some(Duration::from_secs(10)), &mut data, &threads), 3);
+
+        // unknown durations count as UNKNOWN_TRACK_DURATION
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 1);
+        data.settings.prefetch_secs = 300;
+        assert_eq!(fill(None, &mut data, &threads), 2);
+    }
+
+    #[test]
+    fn same_seed_and_state_give_the_same_picks() {
+        let mut stats = TrackStats::default();
+        stats.set_rating(Path::new("/music/artist 0/album 0/00.mp3"), Some(5));
+        // long enough ago for its weight to be back in full, whenever the test runs
+        stats.record_play(
+            Path::new("/music/artist 1/album 2/03.mp3"),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_990_000),
+        );
+        let state = ShuffleState {
+            stats,
+            ..make_state()
+        };
+        for order in [
+            PlayOrder::Random,
+            PlayOrder::ShuffleBag,
+            PlayOrder::Weighted,
+        ] {
+            let picks = |seed| {
+                let (mut data, threads) = make_data(order, state.clone(), seed);
+                pick(60, &mut data, &threads)
+            };
+            let first = picks(42);
+            assert_eq!(first.len(), 60);
+            assert_eq!(first, picks(42), "{order:?}");
+            assert_ne!(first, picks(43), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn replay_starts_from_the_recorded_state() {
+        let recorded = make_state();
+        let (mut data, threads) = make_data(PlayOrder::Random, recorded.clone(), 7);
+        let played = pick(30, &mut data, &threads);
+
+        // the session changed the history the picks avoid, the seed alone isn't enough
+        let current = ShuffleState {
+            history: data.shuffle_history.clone(),
+            bag: data.shuffle_bag.clone(),
+            stats: data.track_stats.clone(),
+        };
+        let (mut data, threads) = make_data(PlayOrder::Random, current, 7);
+        assert_ne!(pick(30, &mut data, &threads), played);
+
+        let (mut data, threads) = make_data(PlayOrder::Random, recorded, 7);
+        assert_eq!(pick(30, &mut data, &threads), played);
+    }
+
+    #[test]
+    fn a_paused_session_stays_paused_when_the_scan_finishes() {
+        for paused in [true, false] {
+            let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 1);
+            // nothing indexed before the scan
+            data.music_dir = None;
+            data.paused = paused;
+            data.settings.music_roots = vec![MusicRoot {
+                path: "/music".to_string(),
+                enabled: true,
+            }];
+            data.scan = Some(RunningScan {
+                id: 1,
+                cancel: Arc::default(),
+                pending_changes: vec![],
+            });
+            let index = data.library_index.clone();
+            finish_scan(index, vec![], &mut data);
+
+            assert!(data.music_dir.is_some());
+            assert_eq!(take_loads(&threads).len(), 1);
+            let played = threads
+                .player
+                .try_iter()
+                .any(|req| matches!(req, player_messages::Request::Play));
+            assert_eq!(played, !paused);
+        }
+    }
+
+    #[test]
+    fn picks_keep_the_no_repeat_window() {
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 3);
+        // 25% of the 120 tracks
+        let picks = pick(500, &mut data, &threads);
+        for (i, path) in picks.iter().enumerate() {
+            assert!(
+                !picks[i.saturating_sub(30)..i].contains(path),
+                "pick {i}: {path:?} repeated"
+            );
+        }
+    }
+
+    // a scan finding the library in `chunks` parts, with a few picks after each of them
+    fn scan(chunks: usize, data: &mut ThreadData, threads: &Threads) -> Vec<PathBuf> {
+        let entries: Vec<IndexEntry> = data
+            .library_index
+            .get_tracks_in(&[PathBuf::from("/music")])
+            .into_iter()
+            .cloned()
+            .collect();
+        data.library_index = LibraryIndex::default();
+        data.music_dir = None;
+        data.settings.music_roots = vec![MusicRoot {
+            path: "/music".to_string(),
+            enabled: true,
+        }];
+        data.scan = Some(RunningScan {
+            id: 1,
+            cancel: Arc::default(),
+            pending_changes: vec![],
+        });
+        let mut picks = vec![];
+        for chunk in entries.chunks(entries.len().div_ceil(chunks)) {
+            let found = scanner_messages::Event::Found(1, chunk.to_vec());
+            handle_scan_event(Ok(found), data);
+            // the first pick is made as soon as a track is found
+            picks.extend(take_loads(threads));
+            data.loading_tracks = 0;
+            picks.extend(pick(4, data, threads));
+        }
+        picks
+    }
+
+    #[test]
+    fn replay_plays_the_recorded_picks_whenever_the_scan_finds_the_tracks() {
+        let dir = make_data_dir();
+        let recorded = Replay {
+            seed: 5,
+            state: make_state(),
+            session: Session::default(),
+            picks: vec![],
+        };
+        replay::write(&dir, &recorded);
+        let (mut data, threads) = make_data_in(dir.clone(), PlayOrder::Random, make_state(), 5);
+        let played = scan(4, &mut data, &threads);
+        assert_eq!(played.len(), 17);
+        record_play(&played[0], &mut data);
+
+        // the seed alone picks from whatever was found at the time
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 5);
+        let mut picks = scan(1, &mut data, &threads);
+        picks.extend(pick(12, &mut data, &threads));
+        assert_ne!(picks, played);
+
+        let files = |dir: &Path| {
+            let mut files: Vec<_> = fs::read_dir(dir)
+                .unwrap()
+                .map(|e| {
+                    let path = e.unwrap().path();
+                    let content = fs::read(&path).unwrap();
+                    (path, content)
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let before = files(&dir);
+
+        let replay = replay::read(&dir).unwrap();
+        assert_eq!(replay.picks, played);
+        let (mut data, threads) = make_data_in(dir.clone(), PlayOrder::Random, replay.state, 5);
+        data.replayed_picks = Some(replay.picks.into());
+        let mut picks = scan(1, &mut data, &threads);
+        picks.extend(pick(12, &mut data, &threads));
+        assert_eq!(picks, played);
+        // then goes on from the seed, keeping the state in memory
+        assert_eq!(pick(5, &mut data, &threads).len(), 5);
+        record_play(&played[1], &mut data);
+        save_session(&mut data);
+        assert_eq!(files(&dir), before);
+    }
+
+    #[test]
+    fn queue_counts_go_past_255_tracks() {
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 1);
+        // the 120 tracks of the library, twice, up to MAX_QUEUE_LEN
+        for _ in 0..2 {
+            handle_request(
+                Ok(messages::Request::Enqueue(vec![PathBuf::from("/music")])),
+                &mut data,
+            );
+        }
+        assert_eq!(take_loads(&threads).len(), 200);
+        assert_eq!(data.loading_tracks, 200);
+
+        data.loading_tracks = 0;
+        data.queued_tracks = 300;
+        handle_player_event(Ok(player_messages::Event::TracksEvicted(280)), &mut data);
+        assert_eq!(data.queued_tracks, 20);
+        // tracks finishing after a clear
+        data.queued_tracks = 0;
+        handle_player_event(Ok(player_messages::Event::TrackFinished), &mut data);
+        handle_player_event(Ok(player_messages::Event::TrackRemoved(0)), &mut data);
+        assert_eq!(data.queued_tracks, 0);
+    }
+
+    #[test]
+    fn only_the_tracks_asked_for_are_restored_as_such() {
+        let (mut data, threads) = make_data(PlayOrder::SequentialFolder, make_state(), 1);
+        let track = |n: usize| PathBuf::from(format!("/music/artist 0/album 0/{n:02}.mp3"));
+        let session = Session {
+            current: Some(track(0)),
+            position: Duration::from_secs(10),
+            paused: false,
+            upcoming: vec![track(5), track(1), track(6), track(2)],
+            explicit: vec![true, false, true],
+        };
+        restore_session(session, &mut data);
+
+        assert_eq!(
+            take_loads(&threads),
+            vec![track(0), track(5), track(1), track(6), track(2)]
+        );
+        let explicit: Vec<_> = data.explicit_loads.iter().map(|(p, _)| p.clone()).collect();
+        assert_eq!(explicit, vec![track(5), track(6)]);
+        // the order goes on after the last restored pick
+        assert_eq!(data.last_picked, Some(track(2)));
+        assert_eq!(data.loading_tracks, 5);
+    }
+
+    #[test]
+    fn restored_tracks_are_at_most_a_full_queue() {
+        let (mut data, threads) = make_data(PlayOrder::Random, make_state(), 1);
+        let session = Session {
+            current: Some(PathBuf::from("/music/artist 0/album 0/00.mp3")),
+            upcoming: vec![PathBuf::from("/music/artist 1/album 0/00.mp3"); 300],
+            explicit: vec![true; 300],
+            ..Session::default()
+        };
+        restore_session(session, &mut data);
+        assert_eq!(take_loads(&threads).len(), MAX_QUEUE_LEN);
+        assert_eq!(data.loading_tracks, MAX_QUEUE_LEN);
+    }
+
+    #[test]
+    fn skipping_an_album_keeps_its_tracks_asked_for() {
+        let (mut data, threads) = make_data(PlayOrder::SequentialTree, make_state(), 1);
+        let track = |n: usize| PathBuf::from(format!("/music/artist 0/album 0/{n:02}.mp3"));
+        data.current_track = Some(track(3));
+        data.explicit_loads = vec![(track(7), Placement::AfterQueued)];
+        data.loading_tracks = 2;
+        handle_request(Ok(messages::Request::SkipAlbum), &mut data);
+        let requests: Vec<_> = threads.player.try_iter().collect();
+        assert!(matches!(
+            &requests[..],
+            [player_messages::Request::EvictRandomAt(paths), player_messages::Request::Skip]
+                if *paths == (0..10).map(track).collect::<Vec<_>>()
+        ));
+
+        // the loads in flight: the one asked for is still queued, the pick is dropped
+        let wav = track_source::tests::write_wav("skipped-album", 1, 8000, &[0.0; 800]);
+        let response = |path| {
+            let source = TrackSource::open(&wav).unwrap();
+            loader_messages::Response::Track(path, source, Arc::default())
+        };
+        handle_load_response(Ok(response(track(7))), &mut data);
+        handle_load_response(Ok(response(track(8))), &mut data);
+        let requests: Vec<_> = threads.player.try_iter().collect();
+        assert!(matches!(
+            &requests[..],
+            [player_messages::Request::EnqueueExplicit(path, _, _, Placement::AfterQueued)]
+                if *path == track(7)
+        ));
+        assert_eq!(data.queued_tracks, 1);
+        // another album is picked instead
+        let loads = take_loads(&threads);
+        assert!(!loads.is_empty());
+        assert!(loads
+            .iter()
+            .all(|p| !p.starts_with("/music/artist 0/album 0")));
+    }
+
+    #[test]
+    fn watcher_changes_are_saved_once_they_settle() {
+        let (mut data, _threads) = make_data(PlayOrder::Random, make_state(), 1);
+        let index_path = data.data_dir.join(crate::LIBRARY_INDEX_RELATIVE_PATH);
+        update_library(vec![PathBuf::from("/music/gone.mp3")], &mut data);
+        update_library(vec![PathBuf::from("/music/gone too.mp3")], &mut data);
+        save_pending_changes(&mut data);
+        assert!(!index_path.exists());
+
+        data.index_changed_at = Some(Instant::now() - INDEX_WRITE_DELAY);
+        save_pending_changes(&mut data);
+        assert!(index_path.exists());
+        assert_eq!(data.index_changed_at, None);
+    }
+
+    #[test]
+    fn picks_are_saved_now_and_then_and_on_an_order_change() {
+        let (mut data, threads) = make_data(PlayOrder::ShuffleBag, make_state(), 1);
+        let bag_path = data.data_dir.join(crate::SHUFFLE_BAG_RELATIVE_PATH);
+        let history_path = data.data_dir.join(crate::SHUFFLE_HISTORY_RELATIVE_PATH);
+        pick(3, &mut data, &threads);
+        save_pending_changes(&mut data);
+        assert!(!bag_path.exists() && !history_path.exists());
+
+        data.shuffle_changed_at = Some(Instant::now() - SHUFFLE_SAVE_INTERVAL);
+        save_pending_changes(&mut data);
+        assert_eq!(
+            shuffle_history::read(&data.data_dir).get_recent(3).count(),
+            3
+        );
+        assert_eq!(data.shuffle_changed_at, None);
+
+        // the bag is saved before the order changes
+        pick(2, &mut data, &threads);
+        fs::remove_file(&bag_path).unwrap();
+        handle_request(
+            Ok(messages::Request::SetPlayOrder(PlayOrder::Random)),
+            &mut data,
+        );
+        assert!(bag_path.exists());
+        assert_eq!(
+            shuffle_history::read(&data.data_dir).get_recent(10).count(),
+            5
+        );
+        assert_eq!(data.shuffle_changed_at, None);
+    }
+
+    #[test]
+    fn exclusions_are_explained() {
+        let (mut data, _threads) = make_data(PlayOrder::Random, make_state(), 1);
+        data.settings.music_roots = vec![MusicRoot {
+            path: "/music".to_string(),
+            enabled: true,
+        }];
+        data.settings.exclude_globs = vec!["album 3".to_string()];
+        data.settings.min_track_duration_secs = 300;
+        data.scan_rules = ScanRules::new(&data.settings);
+        let explain = |path: &str| explain_exclusion(Path::new(path), &data);
+
+        assert_eq!(
+            explain("/other/01.mp3"),
+            "not inside an enabled music folder"
+        );
+        assert_eq!(
+            explain("/music/artist 0/album 3/01.mp3"),
+            "excluded: matches the exclude glob 'album 3' in settings"
+        );
+        assert_eq!(
+            explain("/music/artist 0/album 0/00.mp3"),
+            "excluded: shorter than the minimum of 300s"
+        );
+        assert_eq!(
+            explain("/music/artist 0/album 0/new.mp3"),
+            "not indexed yet (press 🔀 to rescan)"
+        );
+
+        data.settings.min_track_duration_secs = 0;
+        data.scan_rules = ScanRules::new(&data.settings);
+        assert_eq!(
+            explain_exclusion(Path::new("/music/artist 0/album 0/00.mp3"), &data),
+            "included"
+        );
+    }
+}
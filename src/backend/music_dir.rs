@@ -1,38 +1,108 @@
-use std::fs::read_dir;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
+use crate::audio_format::AudioFormat;
+use crate::backend::library_index::LibraryIndex;
+use crate::backend::scan_rules::ScanRules;
+use crate::backend::weighted_sampler::WeightedSampler;
 use crate::music_dir_creation_error::MusicDirCreationError;
-use rand::random;
+use crate::settings::ShuffleWeighting;
+use rand::Rng;
+
+// the window is at most all the tracks but one, so a free one is usually found quickly
+const MAX_PICK_ATTEMPTS: usize = 100;
+// the weights depend on the time since a track was played, so they are computed again now and then
+const REWEIGHT_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 pub struct MusicDir {
     sub_dirs: Vec<Rc<MusicDir>>,
     track_paths: Vec<PathBuf>,
+    track_count: usize, // tracks in this folder and all its sub-folders
+    album_count: usize, // folders holding tracks, this one included
+    weighted: RefCell<Option<WeightedTracks>>, // built on the first weighted pick
+}
+
+// the weights of all the tracks of the tree, in the order of get_track
+struct WeightedTracks {
+    sampler: WeightedSampler,
+    positions: HashMap<PathBuf, usize>,
+    built_at: Instant,
 }
 
 impl MusicDir {
-    pub fn new(path: PathBuf) -> Result<Self, MusicDirCreationError> {
-        // println!("Creating {}", path.display());
-        if !path.exists() {
-            // println!("DOESNT EXIST");
-            return Err(MusicDirCreationError::NotFound);
-        }
-        if !path.is_dir() {
-            // println!("NOT DIR");
-            return Err(MusicDirCreationError::NotDir);
-        }
-        let tracks = get_all_mp3s(&path);
-        let sub_dirs = get_sub_dirs(&path);
-        if tracks.is_none() && sub_dirs.is_err() {
-            // println!("EMPTY");
-            Err(MusicDirCreationError::Empty)
+    /// Builds the folder tree of `path` from the indexed tracks.
+    pub fn new(
+        path: &Path,
+        index: &LibraryIndex,
+        formats: &[AudioFormat],
+        rules: &ScanRules,
+        skipped: &HashSet<&PathBuf>, // e.g. duplicates
+    ) -> Result<Self, MusicDirCreationError> {
+        let mut track_paths = index.get_track_paths(path, formats, rules);
+        track_paths.retain(|p| !skipped.contains(p));
+        Self::from_track_paths(path, track_paths).ok_or(MusicDirCreationError::Empty)
+    }
+
+    /// Builds the folder tree of `path` from the given tracks, none if there are none.
+    pub fn from_track_paths(path: &Path, paths: Vec<PathBuf>) -> Option<Self> {
+        let mut track_paths = vec![];
+        let mut sub_dir_paths: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+
+        for track_path in paths {
+            if track_path.parent() == Some(path) {
+                track_paths.push(track_path);
+                continue;
+            }
+            // group by the sub-folder directly inside this one
+            let Ok(relative) = track_path.strip_prefix(path) else {
+                continue;
+            };
+            if let Some(first) = relative.components().next() {
+                sub_dir_paths
+                    .entry(path.join(first))
+                    .or_default()
+                    .push(track_path);
+            }
+        }
+
+        let sub_dirs: Vec<_> = sub_dir_paths
+            .into_iter()
+            .filter_map(|(sub_path, paths)| Self::from_track_paths(&sub_path, paths))
+            .map(Rc::new)
+            .collect();
+
+        if track_paths.is_empty() && sub_dirs.is_empty() {
+            None
         } else {
-            // println!("{} created!!!!!", path.display());
-            Ok(Self {
-                //path,
-                sub_dirs: sub_dirs.unwrap_or_default(),
-                track_paths: tracks.unwrap_or_default(),
-            })
+            track_paths.sort();
+            Some(Self::with_counts(sub_dirs, track_paths))
+        }
+    }
+
+    /// Joins the trees of several roots. The tracks directly inside each root become
+    /// a folder of their own, so every root keeps its own top-level folders.
+    pub fn merge(roots: Vec<MusicDir>) -> Self {
+        let mut sub_dirs = vec![];
+        for root in roots {
+            if root.has_tracks() {
+                sub_dirs.push(Rc::new(Self::with_counts(vec![], root.track_paths)));
+            }
+            sub_dirs.extend(root.sub_dirs);
+        }
+        Self::with_counts(sub_dirs, vec![])
+    }
+
+    fn with_counts(sub_dirs: Vec<Rc<MusicDir>>, track_paths: Vec<PathBuf>) -> Self {
+        let own_album = usize::from(!track_paths.is_empty());
+        Self {
+            track_count: track_paths.len() + sub_dirs.iter().map(|d| d.track_count).sum::<usize>(),
+            album_count: own_album + sub_dirs.iter().map(|d| d.album_count).sum::<usize>(),
+            sub_dirs,
+            track_paths,
+            weighted: RefCell::new(None),
         }
     }
 
@@ -41,80 +111,444 @@ impl MusicDir {
     //     self.track_paths.is_empty() && self.sub_dirs.is_empty()
     // }
 
-    pub fn has_tracks(&self) -> bool {
-        !self.track_paths.is_empty()
+    pub fn get_track_count(&self) -> usize {
+        self.track_count
+    }
+
+    pub fn get_album_count(&self) -> usize {
+        self.album_count
     }
 
-    pub fn has_sub_dirs(&self) -> bool {
-        !self.sub_dirs.is_empty()
+    pub fn get_all_track_paths(&self) -> Vec<&PathBuf> {
+        let mut paths: Vec<&PathBuf> = self.track_paths.iter().collect();
+        for sub_dir in &self.sub_dirs {
+            paths.extend(sub_dir.get_all_track_paths());
+        }
+        paths
     }
 
-    pub fn get_random_track_path(&self) -> Option<PathBuf> {
+    /// Own tracks of every folder holding some.
+    pub fn get_albums(&self) -> Vec<&[PathBuf]> {
+        let mut albums = vec![];
         if self.has_tracks() {
-            let n = get_random_index(&self.track_paths);
-            return Some(self.track_paths[n].clone());
+            albums.push(self.track_paths.as_slice());
         }
-        if self.has_sub_dirs() {
-            let n = get_random_index(&self.sub_dirs);
-            return self.sub_dirs[n].get_random_track_path();
+        for sub_dir in &self.sub_dirs {
+            albums.extend(sub_dir.get_albums());
         }
-        None
+        albums
     }
-}
 
-fn get_all_mp3s(path: &Path) -> Option<Vec<PathBuf>> {
-    let mut res = vec![];
-    let read_dir = read_dir(path).ok()?;
+    pub fn has_tracks(&self) -> bool {
+        !self.track_paths.is_empty()
+    }
+
+    /// Random track accepted by `accept`, none if no track is. When the picks keep being
+    /// refused, one is drawn from the accepted tracks only, with the same relative chances.
+    pub fn get_random_track_path_where(
+        &self,
+        weighting: ShuffleWeighting,
+        rng: &mut impl Rng,
+        accept: impl Fn(&PathBuf) -> bool,
+    ) -> Option<PathBuf> {
+        let picked = (0..MAX_PICK_ATTEMPTS)
+            .filter_map(|_| self.get_random_track_path(weighting, rng))
+            .find(|path| accept(path));
+        if picked.is_some() {
+            return picked;
+        }
+        // e.g. most of the library is by the artists of the last picks
+        let accepted: Vec<_> = self
+            .get_pick_chances(weighting)
+            .into_iter()
+            .filter(|(path, _)| accept(path))
+            .collect();
+        let sampler = WeightedSampler::new(accepted.iter().map(|(_, chance)| *chance).collect());
+        let n = sampler.sample(rng)?;
+        Some(accepted[n].0.clone())
+    }
 
-    for entry in read_dir.flatten() {
-        let path_buf = entry.path();
-        if let Some(ext) = path_buf.extension() {
-            if ext == "mp3" {
-                res.push(path_buf);
+    // every track with its chance of being picked, up to a common factor
+    fn get_pick_chances(&self, weighting: ShuffleWeighting) -> Vec<(&PathBuf, f64)> {
+        match weighting {
+            ShuffleWeighting::Track => uniform(self.get_all_track_paths()).collect(),
+            ShuffleWeighting::AlbumFolder => self
+                .get_albums()
+                .into_iter()
+                .flat_map(|album| uniform(album.iter().collect()))
+                .collect(),
+            ShuffleWeighting::TopLevelFolder => {
+                let mut chances: Vec<_> = if self.has_tracks() {
+                    uniform(self.track_paths.iter().collect()).collect()
+                } else {
+                    vec![]
+                };
+                for sub_dir in &self.sub_dirs {
+                    chances.extend(uniform(sub_dir.get_all_track_paths()));
+                }
+                chances
             }
         }
     }
 
-    if res.is_empty() {
-        None
-    } else {
-        Some(res)
+    /// Random track that is not in `avoided`, unless the picks keep landing in it.
+    pub fn get_random_track_path_avoiding(
+        &self,
+        weighting: ShuffleWeighting,
+        rng: &mut impl Rng,
+        avoided: &HashSet<PathBuf>,
+    ) -> Option<PathBuf> {
+        let mut path = self.get_random_track_path(weighting, rng)?;
+        for _ in 1..MAX_PICK_ATTEMPTS {
+            if !avoided.contains(&path) {
+                break;
+            }
+            path = self.get_random_track_path(weighting, rng)?;
+        }
+        Some(path)
+    }
+
+    /// Random track with a chance proportional to its `weight`, not in `avoided`
+    /// unless the picks keep landing in it.
+    pub fn get_weighted_random_track_path(
+        &self,
+        weight: impl Fn(&Path) -> f64,
+        rng: &mut impl Rng,
+        avoided: &HashSet<PathBuf>,
+    ) -> Option<PathBuf> {
+        let mut weighted = self.weighted.borrow_mut();
+        if weighted
+            .as_ref()
+            .is_none_or(|w| w.built_at.elapsed() > REWEIGHT_INTERVAL)
+        {
+            let paths = self.get_all_track_paths();
+            let weights = paths.iter().map(|p| weight(p)).collect();
+            *weighted = Some(WeightedTracks {
+                sampler: WeightedSampler::new(weights),
+                positions: paths.into_iter().cloned().zip(0..).collect(),
+                built_at: Instant::now(),
+            });
+        }
+        let sampler = &weighted.as_ref().unwrap().sampler;
+        let mut path = self.get_track(sampler.sample(rng)?);
+        for _ in 1..MAX_PICK_ATTEMPTS {
+            if !avoided.contains(path) {
+                break;
+            }
+            path = self.get_track(sampler.sample(rng)?);
+        }
+        Some(path.clone())
+    }
+
+    /// Changes the weight of a track for the next weighted picks.
+    pub fn update_weight(&self, path: &Path, weight: f64) {
+        if let Some(weighted) = self.weighted.borrow_mut().as_mut() {
+            if let Some(&i) = weighted.positions.get(path) {
+                weighted.sampler.set(i, weight);
+            }
+        }
     }
-}
 
-fn get_sub_dirs(path: &Path) -> Result<Vec<Rc<MusicDir>>, MusicDirCreationError> {
-    let mut res = vec![];
-    match read_dir(path) {
-        Ok(dir_iter) => {
-            for entry in dir_iter.flatten() {
-                let path_buf = entry.path();
-                match MusicDir::new(path_buf) {
-                    Ok(music_dir) => {
-                        res.push(Rc::new(music_dir));
-                    }
-                    Err(e) => match e {
-                        MusicDirCreationError::NotFound => {}
-                        MusicDirCreationError::NotDir => {}
-                        MusicDirCreationError::Empty => {}
-                        MusicDirCreationError::Unknown => {
-                            return Err(MusicDirCreationError::Unknown);
-                        }
-                    },
+    pub fn get_random_track_path(
+        &self,
+        weighting: ShuffleWeighting,
+        rng: &mut impl Rng,
+    ) -> Option<PathBuf> {
+        match weighting {
+            ShuffleWeighting::Track => self.get_random_track(rng),
+            ShuffleWeighting::AlbumFolder => {
+                if self.album_count == 0 {
+                    return None;
+                }
+                let n = rng.gen_range(0..self.album_count);
+                let album = self.get_album(n);
+                let n = get_random_index(&album.track_paths, rng);
+                Some(album.track_paths[n].clone())
+            }
+            ShuffleWeighting::TopLevelFolder => {
+                // the tracks directly in a root count as one more folder
+                let own_tracks = usize::from(self.has_tracks());
+                let groups = own_tracks + self.sub_dirs.len();
+                if groups == 0 {
+                    return None;
                 }
+                let n = rng.gen_range(0..groups);
+                if n < own_tracks {
+                    let n = get_random_index(&self.track_paths, rng);
+                    Some(self.track_paths[n].clone())
+                } else {
+                    self.sub_dirs[n - own_tracks].get_random_track(rng)
+                }
+            }
+        }
+    }
+
+    // uniform over all the tracks of the tree
+    fn get_random_track(&self, rng: &mut impl Rng) -> Option<PathBuf> {
+        if self.track_count == 0 {
+            return None;
+        }
+        let n = rng.gen_range(0..self.track_count);
+        Some(self.get_track(n).clone())
+    }
+
+    // n-th track of the tree, own tracks first, then the sub-folders in order
+    fn get_track(&self, mut n: usize) -> &PathBuf {
+        if n < self.track_paths.len() {
+            return &self.track_paths[n];
+        }
+        n -= self.track_paths.len();
+        for sub_dir in &self.sub_dirs {
+            if n < sub_dir.track_count {
+                return sub_dir.get_track(n);
             }
-            if res.is_empty() {
-                Err(MusicDirCreationError::Empty)
-            } else {
-                Ok(res)
+            n -= sub_dir.track_count;
+        }
+        unreachable!("track index out of range")
+    }
+
+    // n-th folder holding tracks, this one first, then the sub-folders in order.
+    // Only the own tracks of the returned folder belong to the album.
+    fn get_album(&self, mut n: usize) -> &MusicDir {
+        if self.has_tracks() {
+            if n == 0 {
+                return self;
             }
+            n -= 1;
         }
-        Err(e) => {
-            eprintln!("Error in reading dir {}: {e}", path.display());
-            Err(MusicDirCreationError::Unknown)
+        for sub_dir in &self.sub_dirs {
+            if n < sub_dir.album_count {
+                return sub_dir.get_album(n);
+            }
+            n -= sub_dir.album_count;
         }
+        unreachable!("album index out of range")
     }
 }
 
-fn get_random_index<T>(v: &[T]) -> usize {
-    random::<usize>() % v.len()
+// the same chance for every path, summing to 1
+fn uniform(paths: Vec<&PathBuf>) -> impl Iterator<Item = (&PathBuf, f64)> {
+    let chance = 1.0 / paths.len() as f64;
+    paths.into_iter().map(move |p| (p, chance))
+}
+
+fn get_random_index<T>(v: &[T], rng: &mut impl Rng) -> usize {
+    rng.gen_range(0..v.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const PICKS: usize = 100_000;
+    // chi-square values with a 0.1% chance of being exceeded by a fair pick
+    const CHI_SQUARE_4_DOF: f64 = 18.47;
+    const CHI_SQUARE_499_DOF: f64 = 603.0;
+
+    // a 1-track folder next to a 500-track one, tracks directly in the root,
+    // and a folder holding a track beside its own sub-folder
+    fn make_tree() -> MusicDir {
+        let mut paths = vec![
+            PathBuf::from("/music/small/a.mp3"),
+            PathBuf::from("/music/loose 1.mp3"),
+            PathBuf::from("/music/loose 2.mp3"),
+            PathBuf::from("/music/artist/single.mp3"),
+        ];
+        paths.extend((0..500).map(|i| PathBuf::from(format!("/music/big/{i:03}.mp3"))));
+        paths.extend((0..9).map(|i| PathBuf::from(format!("/music/artist/album/{i}.mp3"))));
+        MusicDir::from_track_paths(Path::new("/music"), paths).unwrap()
+    }
+
+    // picks per track
+    fn pick(dir: &MusicDir, weighting: ShuffleWeighting) -> HashMap<PathBuf, usize> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = HashMap::new();
+        for _ in 0..PICKS {
+            let path = dir.get_random_track_path(weighting, &mut rng).unwrap();
+            *counts.entry(path).or_default() += 1;
+        }
+        counts
+    }
+
+    fn count_by_folder(counts: &HashMap<PathBuf, usize>) -> HashMap<&Path, usize> {
+        let mut by_folder = HashMap::new();
+        for (path, n) in counts {
+            *by_folder.entry(path.parent().unwrap()).or_default() += n;
+        }
+        by_folder
+    }
+
+    fn chi_square(observed: impl Fn(&str) -> usize, expected: &[(&str, f64)]) -> f64 {
+        expected
+            .iter()
+            .map(|&(key, p)| {
+                let e = p * PICKS as f64;
+                (observed(key) as f64 - e).powi(2) / e
+            })
+            .sum()
+    }
+
+    fn check_folders(counts: &HashMap<PathBuf, usize>, expected: &[(&str, f64)]) {
+        let by_folder = count_by_folder(counts);
+        assert_eq!(by_folder.len(), expected.len());
+        let observed = |folder: &str| by_folder[Path::new(folder)];
+        let x2 = chi_square(observed, expected);
+        assert!(x2 < CHI_SQUARE_4_DOF, "chi-square {x2} for {by_folder:?}");
+    }
+
+    // within the big folder, every track as likely as the others
+    fn check_big_folder_uniform(counts: &HashMap<PathBuf, usize>) {
+        let total: usize = (0..500)
+            .map(|i| counts[&PathBuf::from(format!("/music/big/{i:03}.mp3"))])
+            .sum();
+        let e = total as f64 / 500.0;
+        let x2: f64 = (0..500)
+            .map(|i| {
+                let n = counts[&PathBuf::from(format!("/music/big/{i:03}.mp3"))];
+                (n as f64 - e).powi(2) / e
+            })
+            .sum();
+        assert!(x2 < CHI_SQUARE_499_DOF, "chi-square {x2}");
+    }
+
+    #[test]
+    fn counts() {
+        let dir = make_tree();
+        assert_eq!(dir.get_track_count(), 513);
+        assert_eq!(dir.get_album_count(), 5);
+    }
+
+    #[test]
+    fn track_weighting_is_uniform_over_tracks() {
+        let counts = pick(&make_tree(), ShuffleWeighting::Track);
+        check_folders(
+            &counts,
+            &[
+                ("/music", 2.0 / 513.0),
+                ("/music/small", 1.0 / 513.0),
+                ("/music/big", 500.0 / 513.0),
+                ("/music/artist", 1.0 / 513.0),
+                ("/music/artist/album", 9.0 / 513.0),
+            ],
+        );
+        check_big_folder_uniform(&counts);
+    }
+
+    #[test]
+    fn album_folder_weighting_is_uniform_over_folders() {
+        let counts = pick(&make_tree(), ShuffleWeighting::AlbumFolder);
+        check_folders(
+            &counts,
+            &[
+                ("/music", 0.2),
+                ("/music/small", 0.2),
+                ("/music/big", 0.2),
+                ("/music/artist", 0.2),
+                ("/music/artist/album", 0.2),
+            ],
+        );
+        check_big_folder_uniform(&counts);
+    }
+
+    #[test]
+    fn top_level_folder_weighting_is_uniform_over_top_level_folders() {
+        let counts = pick(&make_tree(), ShuffleWeighting::TopLevelFolder);
+        // the tracks directly in the root count as a folder, and inside "artist"
+        // the picks are uniform over its 10 tracks
+        check_folders(
+            &counts,
+            &[
+                ("/music", 0.25),
+                ("/music/small", 0.25),
+                ("/music/big", 0.25),
+                ("/music/artist", 0.25 / 10.0),
+                ("/music/artist/album", 0.25 * 9.0 / 10.0),
+            ],
+        );
+        check_big_folder_uniform(&counts);
+    }
+
+    #[test]
+    fn pick_chances_follow_the_weighting() {
+        let dir = make_tree();
+        let chance = |weighting, path: &str| {
+            let chances = dir.get_pick_chances(weighting);
+            chances
+                .iter()
+                .find(|(p, _)| *p == Path::new(path))
+                .unwrap()
+                .1
+        };
+        let total =
+            |weighting| -> f64 { dir.get_pick_chances(weighting).iter().map(|c| c.1).sum() };
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(total(ShuffleWeighting::Track), 1.0));
+        assert!(close(
+            chance(ShuffleWeighting::Track, "/music/small/a.mp3"),
+            1.0 / 513.0
+        ));
+        // every album sums to 1, and so does every top-level folder
+        assert!(close(total(ShuffleWeighting::AlbumFolder), 5.0));
+        assert!(close(
+            chance(ShuffleWeighting::AlbumFolder, "/music/small/a.mp3"),
+            1.0
+        ));
+        assert!(close(
+            chance(ShuffleWeighting::AlbumFolder, "/music/big/000.mp3"),
+            1.0 / 500.0
+        ));
+        assert!(close(total(ShuffleWeighting::TopLevelFolder), 4.0));
+        assert!(close(
+            chance(ShuffleWeighting::TopLevelFolder, "/music/artist/single.mp3"),
+            0.1
+        ));
+        assert!(close(
+            chance(ShuffleWeighting::TopLevelFolder, "/music/loose 1.mp3"),
+            0.5
+        ));
+    }
+
+    #[test]
+    fn refused_picks_fall_back_to_the_accepted_tracks() {
+        let dir = make_tree();
+        let first = PathBuf::from("/music/big/000.mp3");
+        let second = PathBuf::from("/music/big/001.mp3");
+        let accept = |p: &PathBuf| *p == first || *p == second;
+        let mut rng = StdRng::seed_from_u64(7);
+        // the 100 random picks miss both tracks 2 times out of 3
+        let mut first_picks = 0;
+        for _ in 0..2000 {
+            let path = dir
+                .get_random_track_path_where(ShuffleWeighting::Track, &mut rng, accept)
+                .unwrap();
+            assert!(accept(&path));
+            first_picks += usize::from(path == first);
+        }
+        assert!((900..1100).contains(&first_picks), "{first_picks}");
+
+        let none = dir.get_random_track_path_where(ShuffleWeighting::Track, &mut rng, |_| false);
+        assert!(none.is_none());
+    }
+
+    #[test]
+    fn merged_roots_keep_their_own_tracks_as_a_folder() {
+        let root = |name: &str| {
+            let paths = vec![
+                PathBuf::from(format!("/{name}/a.mp3")),
+                PathBuf::from(format!("/{name}/album/b.mp3")),
+            ];
+            MusicDir::from_track_paths(Path::new(&format!("/{name}")), paths).unwrap()
+        };
+        let merged = MusicDir::merge(vec![root("one"), root("two")]);
+        assert_eq!(merged.get_track_count(), 4);
+        assert_eq!(merged.get_album_count(), 4);
+        let counts = pick(&merged, ShuffleWeighting::TopLevelFolder);
+        assert_eq!(counts.len(), 4);
+        for n in counts.values() {
+            let share = *n as f64 / PICKS as f64;
+            assert!((share - 0.25).abs() < 0.01, "{counts:?}");
+        }
+    }
 }
@@ -1,44 +1,98 @@
 use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
 use std::time::Duration;
 
 use crossbeam_channel::{select, unbounded, Receiver, RecvError, Sender};
-use rodio::source::EmptyCallback;
-use rodio::{Decoder, Sink};
+use rodio::Sink;
 
-use crate::backend::player_messages::{Event, Request};
+use crate::backend::player_messages::{Event, Fades, Placement, Request};
+use crate::backend::track_chain::{ChainEvent, ChainHandle, TrackChain};
+use crate::backend::track_source::TrackSource;
 use crate::track_metadata::TrackMetaData;
 
+// a track waiting to be appended to the sink
+struct QueuedTrack {
+    path: PathBuf,
+    source: TrackSource,
+    metadata: Arc<TrackMetaData>,
+    explicit: bool, // asked for by the user, not a random pick
+}
+
+// the track following the current one, its source is already in the chain
+struct ArmedTrack {
+    path: PathBuf,
+    metadata: Arc<TrackMetaData>,
+    explicit: bool,
+    replay: bool, // the current track again, for repeat one
+}
+
+// only the current track and the armed one are in the sink, so the upcoming ones can still be evicted
+struct PlayerQueue {
+    current: Option<(PathBuf, Arc<TrackMetaData>)>,
+    armed: Option<ArmedTrack>,
+    upcoming: VecDeque<QueuedTrack>,
+    repeat_one: bool,
+    skipping: bool, // the current track ends because it was skipped, so it is not repeated
+}
+
+// the sink and the chain of tracks playing in it
+struct Output {
+    sink: Sink,
+    chain: Option<ChainHandle>,
+    next_chain_id: u64,
+    channels: u16,
+    sample_rate: u32,
+    chain_event_sender: Sender<ChainEvent>,
+    fades: Fades,
+}
+
 pub fn run(request_receiver: Receiver<Request>, event_sender: Sender<Event>) {
-    // track finished message
-    let (track_finished_sender, track_finished_receiver) = unbounded::<()>();
+    // sent by the chains from the audio thread
+    let (chain_event_sender, chain_event_receiver) = unbounded::<ChainEvent>();
 
-    // track metadata queue
-    let mut track_metadata_queue: VecDeque<Arc<TrackMetaData>> = VecDeque::new();
+    // track queue
+    let mut queue = PlayerQueue {
+        current: None,
+        armed: None,
+        upcoming: VecDeque::new(),
+        repeat_one: false,
+        skipping: false,
+    };
 
     let stream_handle =
         rodio::OutputStreamBuilder::open_default_stream().expect("open default audio stream");
-    let sink = Sink::connect_new(&stream_handle.mixer());
+    let config = stream_handle.config();
+    let mut output = Output {
+        sink: Sink::connect_new(stream_handle.mixer()),
+        chain: None,
+        next_chain_id: 0,
+        channels: config.channel_count(),
+        sample_rate: config.sample_rate(),
+        chain_event_sender,
+        fades: Fades::default(),
+    };
 
     loop {
         select! {
             recv(request_receiver) -> res => handle_request(
                 res,
-                &sink,
-                &track_finished_sender,
+                &mut output,
                 &event_sender,
-                &mut track_metadata_queue,
+                &mut queue,
             ),
-            recv(track_finished_receiver) -> _ => handle_track_finished(
-                &mut track_metadata_queue,
+            recv(chain_event_receiver) -> res => handle_chain_event(
+                res,
+                &mut output,
+                &mut queue,
                 &event_sender,
             ),
             default(Duration::from_millis(100)) => {},
         }
-        if !sink.empty() {
+        if let Some(chain) = &output.chain {
             event_sender
-                .send(Event::ProgressUpdate(sink.get_pos()))
+                .send(Event::ProgressUpdate(chain.get_pos()))
                 .unwrap()
         }
     }
@@ -46,102 +100,408 @@ pub fn run(request_receiver: Receiver<Request>, event_sender: Sender<Event>) {
 
 fn handle_request(
     res: Result<Request, RecvError>,
-    sink: &Sink,
-    track_finished_sender: &Sender<()>,
+    output: &mut Output,
     event_sender: &Sender<Event>,
-    track_metadata_queue: &mut VecDeque<Arc<TrackMetaData>>,
+    queue: &mut PlayerQueue,
 ) {
     match res {
-        Ok(req) => match req {
-            Request::Enqueue(track, metadata) => {
-                let source = Decoder::try_from(track).unwrap();
-                sink.append(source);
-
-                // append empty callback to send track finished signal
-                let sender = track_finished_sender.clone();
-                let ec: EmptyCallback = EmptyCallback::new(Box::new(move || {
-                    sender.send(()).unwrap();
-                }));
-                sink.append(ec);
-
-                track_metadata_queue.push_back(metadata);
-
-                if track_metadata_queue.len() == 1 {
-                    // safe unwrap
-                    let arc_clone = Arc::clone(track_metadata_queue.front().unwrap());
-                    event_sender
-                        .send(Event::NewTrackPlaying(Some(arc_clone)))
-                        .unwrap()
-                }
+        Ok(req) => {
+            let changes_queue = changes_queue(&req);
+            if changes_queue {
+                disarm(output, queue);
+            }
+            apply_request(req, output, event_sender, queue);
+            if changes_queue {
+                arm(output, queue);
             }
-            Request::Play => {
-                println!("Player thread: received play");
-                println!("Sink is paused: {0}", sink.is_paused());
-                sink.play();
-                println!("Sink is paused: {0}", sink.is_paused());
+        }
+        // TODO: handle this
+        Err(e) => {
+            eprintln!("Error in handle request: {e:?}");
+            exit(1);
+        }
+    }
+}
+
+// whether the request may change the track following the current one
+fn changes_queue(req: &Request) -> bool {
+    !matches!(
+        req,
+        Request::Play
+            | Request::Pause
+            | Request::JumpToFraction(_)
+            | Request::JumpTo(_)
+            | Request::Restart
+            | Request::SetVolume(_)
+    )
+}
 
-                event_sender.send(Event::NowPlaying).unwrap();
+fn apply_request(
+    req: Request,
+    output: &mut Output,
+    event_sender: &Sender<Event>,
+    queue: &mut PlayerQueue,
+) {
+    match req {
+        Request::Enqueue(path, source, metadata) => {
+            queue.upcoming.push_back(QueuedTrack {
+                path,
+                source,
+                metadata,
+                explicit: false,
+            });
+            if queue.current.is_none() {
+                play_next(output, queue, event_sender);
+            } else {
+                send_queue(queue, event_sender);
             }
-            Request::Pause => {
-                println!("Player thread: received pause");
-                println!("Sink is paused: {0}", sink.is_paused());
-                sink.pause();
-                println!("Sink is paused: {0}", sink.is_paused());
+        }
+        Request::Insert(i, path, source, metadata) => {
+            let i = i.min(queue.upcoming.len());
+            queue.upcoming.insert(
+                i,
+                QueuedTrack {
+                    path,
+                    source,
+                    metadata,
+                    explicit: false,
+                },
+            );
+            if queue.current.is_none() {
+                play_next(output, queue, event_sender);
+            } else {
+                send_queue(queue, event_sender);
+            }
+        }
+        Request::EnqueueExplicit(path, source, metadata, placement) => {
+            let i = match placement {
+                Placement::Now | Placement::Next => 0,
+                Placement::AfterQueued => queue.upcoming.iter().take_while(|t| t.explicit).count(),
+            };
+            queue.upcoming.insert(
+                i,
+                QueuedTrack {
+                    path,
+                    source,
+                    metadata,
+                    explicit: true,
+                },
+            );
+            if queue.current.is_none() {
+                play_next(output, queue, event_sender);
+            } else if let Placement::Now = placement {
+                // the new one is at the front, so it follows right away
+                skip(output, queue);
+            } else {
+                send_queue(queue, event_sender);
+            }
+        }
+        Request::Remove(i, path) => {
+            if queue.upcoming.get(i).is_some_and(|t| t.path == path) {
+                queue.upcoming.remove(i);
+                event_sender.send(Event::TrackRemoved(i)).unwrap();
+                send_queue(queue, event_sender);
+            }
+        }
+        Request::Move { from, to, path } => {
+            if queue.upcoming.get(from).is_some_and(|t| t.path == path) {
+                let track = queue.upcoming.remove(from).unwrap();
+                let to = to.min(queue.upcoming.len());
+                queue.upcoming.insert(to, track);
+                send_queue(queue, event_sender);
+            }
+        }
+        Request::Play => {
+            println!("Player thread: received play");
+            println!("Sink is paused: {0}", output.sink.is_paused());
+            output.sink.play();
+            println!("Sink is paused: {0}", output.sink.is_paused());
+
+            event_sender.send(Event::NowPlaying).unwrap();
+        }
+        Request::Pause => {
+            println!("Player thread: received pause");
+            println!("Sink is paused: {0}", output.sink.is_paused());
+            output.sink.pause();
+            println!("Sink is paused: {0}", output.sink.is_paused());
 
-                event_sender.send(Event::NowPaused).unwrap();
+            event_sender.send(Event::NowPaused).unwrap();
+        }
+        Request::JumpToFraction(f) => match queue.current.as_ref().unwrap().1.duration {
+            None => {
+                unreachable!();
             }
-            Request::JumpToFraction(f) => match track_metadata_queue.front().unwrap().duration {
-                None => {
-                    unreachable!();
-                }
-                Some(d) => {
-                    let progress_seconds = d.mul_f32(f);
-                    println!("JUMP TO {progress_seconds:?}");
-                    match sink.try_seek(progress_seconds) {
-                        Ok(_) => {
-                            event_sender
-                                .send(Event::JumpedTo(progress_seconds))
-                                .unwrap();
-                        }
-                        Err(e) => {
-                            println!("ERROR IN SEEK!");
-                            println!("{e}");
-                            exit(1)
-                        }
+            Some(d) => {
+                let progress_seconds = d.mul_f32(f);
+                println!("JUMP TO {progress_seconds:?}");
+                match output.sink.try_seek(progress_seconds) {
+                    Ok(_) => {
+                        event_sender
+                            .send(Event::JumpedTo(progress_seconds))
+                            .unwrap();
+                    }
+                    Err(e) => {
+                        println!("ERROR IN SEEK!");
+                        println!("{e}");
+                        exit(1)
                     }
                 }
-            },
-            Request::Skip => {
-                sink.skip_one();
             }
-            Request::Clear => {
-                sink.clear();
-                track_metadata_queue.clear();
-                event_sender.send(Event::NewTrackPlaying(None)).unwrap()
+        },
+        Request::JumpTo(position) => {
+            if queue.current.is_none() {
+                return;
             }
-            Request::SetVolume(v) => {
-                sink.set_volume(v * v); // adjust volume curve
+            match output.sink.try_seek(position) {
+                Ok(_) => {
+                    event_sender.send(Event::JumpedTo(position)).unwrap();
+                }
+                // e.g. the file got shorter since the position was saved
+                Err(e) => {
+                    eprintln!("Player thread: failed to seek to {position:?}: {e}");
+                }
             }
-        },
-        // TODO: handle this
-        Err(e) => {
-            eprintln!("Error in handle request: {e:?}");
-            exit(1);
+        }
+        Request::Skip => {
+            skip(output, queue);
+        }
+        Request::Restart => {
+            if queue.current.is_none() {
+                return;
+            }
+            match output.sink.try_seek(Duration::ZERO) {
+                Ok(_) => {
+                    event_sender.send(Event::JumpedTo(Duration::ZERO)).unwrap();
+                }
+                Err(e) => {
+                    eprintln!("Player thread: failed to restart the track: {e}");
+                }
+            }
+        }
+        Request::Clear => {
+            output.sink.clear();
+            output.chain = None;
+            queue.current = None;
+            queue.armed = None;
+            queue.upcoming.clear();
+            event_sender.send(Event::NewTrackPlaying(None)).unwrap();
+            send_queue(queue, event_sender);
+        }
+        Request::SetVolume(v) => {
+            output.sink.set_volume(v * v); // adjust volume curve
+        }
+        Request::SetRepeatOne(repeat_one) => {
+            queue.repeat_one = repeat_one;
+        }
+        Request::SetFades(fades) => {
+            output.fades = fades;
+        }
+        Request::Evict(paths) => {
+            evict(queue, event_sender, |t| {
+                paths.iter().any(|p| t.path.starts_with(p))
+            });
+        }
+        Request::EvictRandom => {
+            evict(queue, event_sender, |t| !t.explicit);
+        }
+        Request::EvictRandomAt(paths) => {
+            evict(queue, event_sender, |t| {
+                !t.explicit && paths.iter().any(|p| t.path.starts_with(p))
+            });
         }
     }
 }
 
-fn handle_track_finished(
-    track_metadata_queue: &mut VecDeque<Arc<TrackMetaData>>,
+fn evict(
+    queue: &mut PlayerQueue,
     event_sender: &Sender<Event>,
+    evicted: impl Fn(&QueuedTrack) -> bool,
 ) {
-    event_sender.send(Event::TrackFinished).unwrap();
-    track_metadata_queue.pop_front();
-    let front = track_metadata_queue.front();
-    match front {
-        None => event_sender.send(Event::NewTrackPlaying(None)).unwrap(),
-        Some(metadata) => event_sender
-            .send(Event::NewTrackPlaying(Some(Arc::clone(metadata))))
-            .unwrap(),
+    let before = queue.upcoming.len();
+    queue.upcoming.retain(|t| !evicted(t));
+    let evicted = before - queue.upcoming.len();
+    if evicted > 0 {
+        println!("Player thread: {evicted} queued tracks evicted");
+        event_sender.send(Event::TracksEvicted(evicted)).unwrap();
+        send_queue(queue, event_sender);
+    }
+}
+
+fn handle_chain_event(
+    res: Result<ChainEvent, RecvError>,
+    output: &mut Output,
+    queue: &mut PlayerQueue,
+    event_sender: &Sender<Event>,
+) {
+    let event = match res {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("Error in handle chain event: {e:?}");
+            exit(1);
+        }
+    };
+    let chain_id = output.chain.as_ref().map(|c| c.id);
+    match event {
+        // a chain replaced or cleared since
+        ChainEvent::Switched(id) | ChainEvent::Ended(id) if Some(id) != chain_id => {}
+        // the armed track started right after the current one, with no gap
+        ChainEvent::Switched(_) => {
+            let armed = queue
+                .armed
+                .take()
+                .expect("a chain switches to its armed track");
+            queue.skipping = false;
+            if armed.replay {
+                event_sender.send(Event::JumpedTo(Duration::ZERO)).unwrap();
+            } else {
+                event_sender.send(Event::TrackFinished).unwrap();
+                queue.current = Some((armed.path, armed.metadata));
+                event_sender
+                    .send(Event::NewTrackPlaying(queue.current.clone()))
+                    .unwrap();
+                send_queue(queue, event_sender);
+            }
+            arm(output, queue);
+        }
+        // nothing was armed in time, or the current track was skipped
+        ChainEvent::Ended(_) => {
+            disarm(output, queue);
+            output.chain = None;
+            let skipped = std::mem::take(&mut queue.skipping);
+            if queue.repeat_one && !skipped && replay_current(output, queue) {
+                event_sender.send(Event::JumpedTo(Duration::ZERO)).unwrap();
+            } else {
+                event_sender.send(Event::TrackFinished).unwrap();
+                play_next(output, queue, event_sender);
+            }
+            arm(output, queue);
+        }
     }
 }
+
+/// Gives the chain the track following the current one, so that it starts with no gap.
+fn arm(output: &Output, queue: &mut PlayerQueue) {
+    if queue.armed.is_some() {
+        return;
+    }
+    let (Some(chain), Some((current_path, current_metadata))) = (&output.chain, &queue.current)
+    else {
+        return;
+    };
+    // a skipped track isn't repeated
+    if queue.repeat_one && !queue.skipping {
+        match TrackSource::open(current_path) {
+            Some(source) => {
+                chain.arm(source, Duration::ZERO);
+                queue.armed = Some(ArmedTrack {
+                    path: current_path.clone(),
+                    metadata: current_metadata.clone(),
+                    explicit: false,
+                    replay: true,
+                });
+                return;
+            }
+            None => eprintln!("Player thread: failed to open {current_path:?} again"),
+        }
+    }
+    if let Some(track) = queue.upcoming.pop_front() {
+        // in an album played in order, the tracks may be meant to flow into each other
+        let same_album = track.path.parent() == current_path.parent();
+        let crossfade = if output.fades.gapless_albums && same_album {
+            Duration::ZERO
+        } else {
+            output.fades.crossfade
+        };
+        chain.arm(track.source, crossfade);
+        queue.armed = Some(ArmedTrack {
+            path: track.path,
+            metadata: track.metadata,
+            explicit: track.explicit,
+            replay: false,
+        });
+    }
+}
+
+/// Goes on with the next track, fading over the skip fade.
+fn skip(output: &Output, queue: &mut PlayerQueue) {
+    queue.skipping = true;
+    arm(output, queue);
+    if let Some(chain) = &output.chain {
+        chain.skip(output.fades.skip);
+    }
+}
+
+/// Takes the armed track back to the front of the queue, so that the queue can change.
+fn disarm(output: &Output, queue: &mut PlayerQueue) {
+    let (Some(armed), Some(chain)) = (queue.armed.take(), &output.chain) else {
+        return;
+    };
+    match chain.disarm() {
+        // opened again when armed again
+        Some(_) if armed.replay => {}
+        Some(source) => queue.upcoming.push_front(QueuedTrack {
+            path: armed.path,
+            source,
+            metadata: armed.metadata,
+            explicit: armed.explicit,
+        }),
+        // already playing, the chain tells it soon
+        None => queue.armed = Some(armed),
+    }
+}
+
+/// Plays the current track again from a new file handle, in a new chain.
+fn replay_current(output: &mut Output, queue: &PlayerQueue) -> bool {
+    let Some((path, _)) = &queue.current else {
+        return false;
+    };
+    let Some(source) = TrackSource::open(path) else {
+        eprintln!("Player thread: failed to open {path:?} again");
+        return false;
+    };
+    start_chain(output, source);
+    true
+}
+
+fn play_next(output: &mut Output, queue: &mut PlayerQueue, event_sender: &Sender<Event>) {
+    queue.current = None;
+    if let Some(track) = queue.upcoming.pop_front() {
+        start_chain(output, track.source);
+
+        queue.current = Some((track.path, track.metadata));
+    }
+    event_sender
+        .send(Event::NewTrackPlaying(queue.current.clone()))
+        .unwrap();
+    send_queue(queue, event_sender);
+}
+
+fn start_chain(output: &mut Output, source: TrackSource) {
+    output.next_chain_id += 1;
+    let (chain, handle) = TrackChain::new(
+        output.next_chain_id,
+        source,
+        output.channels,
+        output.sample_rate,
+        output.chain_event_sender.clone(),
+    );
+    output.sink.append(chain);
+    output.chain = Some(handle);
+}
+
+fn send_queue(queue: &PlayerQueue, event_sender: &Sender<Event>) {
+    let armed = queue
+        .armed
+        .iter()
+        .filter(|a| !a.replay)
+        .map(|a| (a.path.clone(), a.metadata.clone(), a.explicit));
+    let upcoming = armed
+        .chain(
+            queue
+                .upcoming
+                .iter()
+                .map(|t| (t.path.clone(), t.metadata.clone(), t.explicit)),
+        )
+        .collect();
+    event_sender.send(Event::QueueChanged(upcoming)).unwrap();
+}
@@ -1,17 +1,49 @@
-use std::fs::File;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::backend::track_source::TrackSource;
 use crate::track_metadata::TrackMetaData;
 
 pub(crate) enum Request {
-    Enqueue(File, Arc<TrackMetaData>),
+    Enqueue(PathBuf, TrackSource, Arc<TrackMetaData>),
+    Insert(usize, PathBuf, TrackSource, Arc<TrackMetaData>), // at this place among the upcoming tracks
+    EnqueueExplicit(PathBuf, TrackSource, Arc<TrackMetaData>, Placement), // asked for by the user
     Play,
     Pause,
     JumpToFraction(f32), // [0, 1]
+    JumpTo(Duration),    // from the start of the current track
     Skip,
+    Restart, // the current track from its beginning
     Clear,
-    SetVolume(f32), // [0, 1]
+    SetVolume(f32),     // [0, 1]
+    SetRepeatOne(bool), // the current track plays again when it ends
+    SetFades(Fades),
+    Evict(Vec<PathBuf>), // drop the upcoming tracks at (or under) these paths
+    EvictRandom,         // drop the upcoming tracks that were not asked for by the user
+    EvictRandomAt(Vec<PathBuf>), // the same, only at (or under) these paths
+    // the path guards against the queue having moved on since the index was read
+    Remove(usize, PathBuf),
+    Move {
+        from: usize,
+        to: usize,
+        path: PathBuf,
+    },
+}
+
+/// Where a track asked for by the user goes. These tracks come before the random picks.
+pub(crate) enum Placement {
+    Now,         // replaces the current track
+    Next,        // right after the current track
+    AfterQueued, // after the other tracks asked for
+}
+
+/// How a track goes on with the next one. A zero fade plays them back to back.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Fades {
+    pub crossfade: Duration,  // when a track ends
+    pub skip: Duration,       // when it is skipped
+    pub gapless_albums: bool, // no crossfade between tracks of the same folder
 }
 
 #[derive(Clone)]
@@ -20,6 +52,10 @@ pub(crate) enum Event {
     NowPlaying,
     NowPaused,
     JumpedTo(Duration),
-    NewTrackPlaying(Option<Arc<TrackMetaData>>),
+    NewTrackPlaying(Option<(PathBuf, Arc<TrackMetaData>)>),
     TrackFinished,
+    TracksEvicted(usize),
+    TrackRemoved(usize), // upcoming track at this index
+    // the upcoming tracks, and whether they were asked for by the user
+    QueueChanged(Vec<(PathBuf, Arc<TrackMetaData>, bool)>),
 }
@@ -0,0 +1,113 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::session::Session;
+use crate::backend::shuffle_bag::{self, ShuffleBag};
+use crate::backend::shuffle_history::{self, ShuffleHistory};
+use crate::backend::track_stats::{self, TrackStats};
+use crate::{REPLAY_PICKS_RELATIVE_PATH, REPLAY_RELATIVE_PATH};
+
+/// What the random picks depend on besides the seed, the library and the settings.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ShuffleState {
+    pub history: ShuffleHistory,
+    pub bag: ShuffleBag,
+    pub stats: TrackStats,
+}
+
+impl ShuffleState {
+    pub fn read(dir: &Path) -> Self {
+        Self {
+            history: shuffle_history::read(dir),
+            bag: shuffle_bag::read(dir),
+            stats: track_stats::read(dir),
+        }
+    }
+}
+
+/// The state a session started from, saved with its seed, and the tracks it picked.
+/// The files it copies change as the session plays, --replay starts from this copy instead.
+/// The picks also depend on when the scan and the watcher changed the library, so a replay
+/// plays the recorded ones before picking from the seed.
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub state: ShuffleState,
+    pub session: Session,
+    #[serde(skip)]
+    pub picks: Vec<PathBuf>, // in their own file, one line each, added as they are made
+}
+
+pub fn read(dir: &Path) -> Option<Replay> {
+    let path = dir.join(REPLAY_RELATIVE_PATH);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            return None;
+        }
+    };
+    let mut replay: Replay = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| eprintln!("Error in parsing {}: {e}", path.display()))
+        .ok()?;
+    replay.picks = read_picks(dir);
+    Some(replay)
+}
+
+// a line cut by a crash ends the picks
+fn read_picks(dir: &Path) -> Vec<PathBuf> {
+    let path = dir.join(REPLAY_PICKS_RELATIVE_PATH);
+    match fs::read_to_string(&path) {
+        Ok(picks) => picks
+            .lines()
+            .map_while(|line| serde_json::from_str(line).ok())
+            .collect(),
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            vec![]
+        }
+    }
+}
+
+/// Without it, --replay reuses the seed with the current state, so failing to write it is not fatal.
+/// The picks of the last session are cleared.
+pub fn write(dir: &Path, replay: &Replay) {
+    let picks_path = dir.join(REPLAY_PICKS_RELATIVE_PATH);
+    if let Err(e) = File::create(&picks_path) {
+        eprintln!("Failed to create file '{}': {e}", picks_path.display());
+    }
+    let path = dir.join(REPLAY_RELATIVE_PATH);
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create file '{}': {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = serde_json::to_writer(BufWriter::new(file), replay) {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
+
+/// Adds a pick to the ones of the session, without writing the whole replay again.
+pub fn record_pick(dir: &Path, pick: &Path) {
+    let path = dir.join(REPLAY_PICKS_RELATIVE_PATH);
+    let line = match serde_json::to_string(pick) {
+        Ok(line) => line,
+        Err(e) => {
+            eprintln!("Failed to record the pick {}: {e}", pick.display());
+            return;
+        }
+    };
+    let res = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{line}"));
+    if let Err(e) = res {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use ignore::Match;
+
+use crate::settings::{HiddenFiles, Settings, SymlinkPolicy};
+
+/// Gitignore-style file honored in every scanned folder.
+pub const IGNORE_FILE_NAME: &str = ".rustifyignore";
+
+/// Why a file or folder is left out of the library.
+#[derive(Debug, Clone)]
+pub enum Exclusion {
+    Hidden,
+    IgnoreFile { file: PathBuf, pattern: String },
+    ExcludeGlob(String),
+    TooShort(Duration),
+}
+
+impl Display for Exclusion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exclusion::Hidden => write!(f, "hidden files are skipped"),
+            Exclusion::IgnoreFile { file, pattern } => {
+                write!(f, "matches '{pattern}' in {}", file.display())
+            }
+            Exclusion::ExcludeGlob(pattern) => {
+                write!(f, "matches the exclude glob '{pattern}' in settings")
+            }
+            Exclusion::TooShort(min) => {
+                write!(f, "shorter than the minimum of {}s", min.as_secs())
+            }
+        }
+    }
+}
+
+/// The rules from the settings.
+#[derive(Clone)]
+pub struct ScanRules {
+    hidden_files: HiddenFiles,
+    name_globs: GlobSet,
+    name_patterns: Vec<String>,
+    path_globs: GlobSet,
+    path_patterns: Vec<String>,
+    min_duration: Duration,
+    symlinks: SymlinkPolicy,
+    max_depth: usize,
+}
+
+impl ScanRules {
+    pub fn new(settings: &Settings) -> Self {
+        let mut name_globs = GlobSetBuilder::new();
+        let mut name_patterns = vec![];
+        let mut path_globs = GlobSetBuilder::new();
+        let mut path_patterns = vec![];
+
+        for pattern in &settings.exclude_globs {
+            let glob = match Glob::new(pattern) {
+                Ok(glob) => glob,
+                Err(e) => {
+                    eprintln!("Invalid exclude glob '{pattern}' in settings: {e}");
+                    continue;
+                }
+            };
+            if pattern.contains('/') {
+                path_globs.add(glob);
+                path_patterns.push(pattern.clone());
+            } else {
+                name_globs.add(glob);
+                name_patterns.push(pattern.clone());
+            }
+        }
+
+        Self {
+            hidden_files: settings.hidden_files,
+            name_globs: name_globs.build().unwrap_or_else(|_| GlobSet::empty()),
+            name_patterns,
+            path_globs: path_globs.build().unwrap_or_else(|_| GlobSet::empty()),
+            path_patterns,
+            min_duration: Duration::from_secs(settings.min_track_duration_secs.into()),
+            symlinks: settings.symlinks,
+            max_depth: settings.max_scan_depth as usize,
+        }
+    }
+
+    pub fn follows_symlinks(&self) -> bool {
+        self.symlinks == SymlinkPolicy::Follow
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Checks the rules that don't depend on the ignore files.
+    pub fn check(&self, path: &Path) -> Option<Exclusion> {
+        let name = path.file_name()?;
+
+        if self.hidden_files == HiddenFiles::Skip && name.to_string_lossy().starts_with('.') {
+            return Some(Exclusion::Hidden);
+        }
+        if let Some(&i) = self.name_globs.matches(name).first() {
+            return Some(Exclusion::ExcludeGlob(self.name_patterns[i].clone()));
+        }
+        if let Some(&i) = self.path_globs.matches(path).first() {
+            return Some(Exclusion::ExcludeGlob(self.path_patterns[i].clone()));
+        }
+        None
+    }
+
+    /// Checks the duration of a track. Tracks of unknown duration are kept.
+    pub fn check_duration(&self, duration: Option<Duration>) -> Option<Exclusion> {
+        match duration {
+            Some(d) if d < self.min_duration => Some(Exclusion::TooShort(self.min_duration)),
+            _ => None,
+        }
+    }
+
+    /// Checks `path` and all its parent folders up to `root`, as a scan from `root` would.
+    pub fn check_from_root(&self, root: &Path, path: &Path, is_dir: bool) -> Option<Exclusion> {
+        let relative = path.strip_prefix(root).ok()?;
+        let mut ignore_files = IgnoreFiles::default().enter(root);
+        let mut current = root.to_path_buf();
+        let mut components = relative.components().peekable();
+
+        while let Some(component) = components.next() {
+            current.push(component);
+            let current_is_dir = components.peek().is_some() || is_dir;
+            if let Some(exclusion) = self
+                .check(&current)
+                .or_else(|| ignore_files.check(&current, current_is_dir))
+            {
+                return Some(exclusion);
+            }
+            if current_is_dir {
+                ignore_files = ignore_files.enter(&current);
+            }
+        }
+        None
+    }
+}
+
+/// The ignore files that apply inside a folder, from the outermost to the innermost.
+#[derive(Clone, Default)]
+pub struct IgnoreFiles(Vec<Arc<Gitignore>>);
+
+impl IgnoreFiles {
+    /// Adds the ignore file of `dir`, if it has one.
+    pub fn enter(&self, dir: &Path) -> IgnoreFiles {
+        let mut res = self.clone();
+        let path = dir.join(IGNORE_FILE_NAME);
+        if path.is_file() {
+            let (gitignore, error) = Gitignore::new(&path);
+            if let Some(e) = error {
+                eprintln!("Error in reading {}: {e}", path.display());
+            }
+            res.0.push(Arc::new(gitignore));
+        }
+        res
+    }
+
+    /// Ignore files of all the folders from `root` down to `dir`, both included.
+    pub fn from_root(root: &Path, dir: &Path) -> IgnoreFiles {
+        let mut res = IgnoreFiles::default().enter(root);
+        if let Ok(relative) = dir.strip_prefix(root) {
+            let mut current = root.to_path_buf();
+            for component in relative.components() {
+                current.push(component);
+                res = res.enter(&current);
+            }
+        }
+        res
+    }
+
+    /// The innermost file with a matching pattern decides, like git does.
+    pub fn check(&self, path: &Path, is_dir: bool) -> Option<Exclusion> {
+        for gitignore in self.0.iter().rev() {
+            match gitignore.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Whitelist(_) => return None,
+                Match::Ignore(glob) => {
+                    return Some(Exclusion::IgnoreFile {
+                        file: gitignore.path().join(IGNORE_FILE_NAME),
+                        pattern: glob.original().to_string(),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn make_rules(exclude_globs: &[&str], hidden_files: HiddenFiles) -> ScanRules {
+        let mut settings = Settings::default();
+        settings.exclude_globs = exclude_globs.iter().map(|g| g.to_string()).collect();
+        settings.hidden_files = hidden_files;
+        settings.min_track_duration_secs = 30;
+        ScanRules::new(&settings)
+    }
+
+    fn explain(rules: &ScanRules, root: &Path, path: &Path) -> Option<String> {
+        rules
+            .check_from_root(root, path, false)
+            .map(|e| e.to_string())
+    }
+
+    #[test]
+    fn name_globs_match_at_any_depth_and_path_globs_whole_paths() {
+        let rules = make_rules(
+            &["*.tmp", "Podcasts", "/music/**/Live/*"],
+            HiddenFiles::Skip,
+        );
+        let root = Path::new("/music");
+        let check = |path: &str| explain(&rules, root, Path::new(path));
+
+        assert_eq!(
+            check("/music/a/b/track.tmp").as_deref(),
+            Some("matches the exclude glob '*.tmp' in settings")
+        );
+        assert_eq!(
+            check("/music/Podcasts/episode.mp3").as_deref(),
+            Some("matches the exclude glob 'Podcasts' in settings")
+        );
+        assert_eq!(
+            check("/music/Artist/Live/01.mp3").as_deref(),
+            Some("matches the exclude glob '/music/**/Live/*' in settings")
+        );
+        assert_eq!(check("/music/Artist/Live.mp3"), None);
+        assert_eq!(check("/music/Artist/My Podcasts/01.mp3"), None);
+        // path globs are absolute, roots elsewhere are not affected
+        assert_eq!(
+            explain(&rules, Path::new("/other"), Path::new("/other/Live/01.mp3")),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_globs_are_left_out() {
+        let rules = make_rules(&["[", "*.tmp"], HiddenFiles::Skip);
+        assert!(rules.check(Path::new("/music/a.tmp")).is_some());
+        assert!(rules.check(Path::new("/music/[")).is_none());
+    }
+
+    #[test]
+    fn hidden_files_are_skipped_unless_included() {
+        let path = Path::new("/music/.cache/01.mp3");
+        let skipped = make_rules(&[], HiddenFiles::Skip);
+        assert_eq!(
+            explain(&skipped, Path::new("/music"), path).as_deref(),
+            Some("hidden files are skipped")
+        );
+        assert!(skipped.check(Path::new("/music/.hidden.mp3")).is_some());
+        let included = make_rules(&[], HiddenFiles::Include);
+        assert_eq!(explain(&included, Path::new("/music"), path), None);
+    }
+
+    #[test]
+    fn inner_ignore_files_whitelist_what_outer_ones_ignore() {
+        let root = env::temp_dir().join(format!("rustify-scan-rules-tests-{}/root", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album/live")).unwrap();
+        fs::write(root.join(IGNORE_FILE_NAME), "*.wav\nlive/\n").unwrap();
+        fs::write(root.join("album").join(IGNORE_FILE_NAME), "!keep.wav\n").unwrap();
+        let rules = make_rules(&[], HiddenFiles::Skip);
+        let check = |path: &str| explain(&rules, &root, &root.join(path));
+
+        assert_eq!(
+            check("a.wav"),
+            Some(format!(
+                "matches '*.wav' in {}",
+                root.join(IGNORE_FILE_NAME).display()
+            ))
+        );
+        assert!(check("album/b.wav").is_some());
+        assert_eq!(check("album/keep.wav"), None);
+        assert_eq!(check("album/a.mp3"), None);
+        // folders are checked on the way down
+        assert_eq!(
+            check("album/live/keep.wav"),
+            Some(format!(
+                "matches 'live/' in {}",
+                root.join(IGNORE_FILE_NAME).display()
+            ))
+        );
+    }
+
+    #[test]
+    fn tracks_shorter_than_the_minimum_are_excluded() {
+        let rules = make_rules(&[], HiddenFiles::Skip);
+        assert_eq!(
+            rules
+                .check_duration(Some(Duration::from_secs(29)))
+                .map(|e| e.to_string())
+                .as_deref(),
+            Some("shorter than the minimum of 30s")
+        );
+        assert!(rules
+            .check_duration(Some(Duration::from_secs(30)))
+            .is_none());
+        // kept when the duration is unknown
+        assert!(rules.check_duration(None).is_none());
+        let no_minimum = ScanRules::new(&Settings::default());
+        assert!(no_minimum.check_duration(Some(Duration::ZERO)).is_none());
+    }
+}
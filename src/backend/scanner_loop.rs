@@ -0,0 +1,93 @@
+use std::mem;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::backend::library_index::{LibraryIndex, ScanStep};
+use crate::backend::scan_rules::ScanRules;
+use crate::backend::scanner_messages::{Event, Request};
+use crate::music_dir_creation_error::MusicDirCreationError;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn run(request_receiver: Receiver<Request>, event_sender: Sender<Event>) {
+    loop {
+        match request_receiver.recv() {
+            Ok(Request::Scan {
+                id,
+                roots,
+                index,
+                rules,
+                cancel,
+            }) => scan(id, roots, index, &rules, &cancel, &event_sender),
+            Err(e) => {
+                println!("Error in scanner thread: {e:?}");
+                exit(1);
+            }
+        }
+    }
+}
+
+fn scan(
+    id: u64,
+    roots: Vec<PathBuf>,
+    mut index: LibraryIndex,
+    rules: &ScanRules,
+    cancel: &AtomicBool,
+    event_sender: &Sender<Event>,
+) {
+    println!("[SCANNER] scan {id} started");
+    let mut dirs = 0;
+    let mut files = 0;
+    let mut current_path = PathBuf::new();
+    let mut found = vec![];
+    let mut last_sent = Instant::now();
+    let mut results = vec![];
+
+    for root in roots {
+        let res = index.rescan(&root, rules, &mut |step| {
+            match step {
+                ScanStep::Dir(path) => {
+                    dirs += 1;
+                    current_path = path.to_path_buf();
+                }
+                ScanStep::File(entry, probed) => {
+                    files += 1;
+                    if probed && entry.format.is_some() {
+                        found.push(entry.clone());
+                    }
+                }
+            }
+            if last_sent.elapsed() >= PROGRESS_INTERVAL {
+                if !found.is_empty() {
+                    event_sender
+                        .send(Event::Found(id, mem::take(&mut found)))
+                        .unwrap();
+                }
+                event_sender
+                    .send(Event::Progress {
+                        id,
+                        dirs,
+                        files,
+                        current_path: current_path.clone(),
+                    })
+                    .unwrap();
+                last_sent = Instant::now();
+            }
+            !cancel.load(Ordering::Relaxed)
+        });
+        if let Err(MusicDirCreationError::Cancelled) = res {
+            println!("[SCANNER] scan {id} cancelled");
+            return;
+        }
+        results.push((root, res));
+    }
+
+    println!("[SCANNER] scan {id} finished: {dirs} folders, {files} files");
+    event_sender
+        .send(Event::Finished { id, index, results })
+        .unwrap();
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::backend::library_index::{IndexEntry, LibraryIndex};
+use crate::backend::scan_rules::ScanRules;
+use crate::music_dir_creation_error::MusicDirCreationError;
+use crate::scan_report::ScanReport;
+
+pub(crate) enum Request {
+    // rescans the roots in a copy of the index, until done or `cancel` is set
+    Scan {
+        id: u64,
+        roots: Vec<PathBuf>,
+        index: LibraryIndex,
+        rules: ScanRules,
+        cancel: Arc<AtomicBool>,
+    },
+}
+
+pub(crate) enum Event {
+    Progress {
+        id: u64,
+        dirs: usize,
+        files: usize,
+        current_path: PathBuf,
+    },
+    Found(u64, Vec<IndexEntry>), // new or changed playable files, to start playing early
+    Finished {
+        id: u64,
+        index: LibraryIndex,
+        results: Vec<(PathBuf, Result<ScanReport, MusicDirCreationError>)>,
+    },
+}
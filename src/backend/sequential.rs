@@ -0,0 +1,351 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::backend::library_index::LibraryIndex;
+use crate::backend::music_dir::MusicDir;
+
+/// Track after `last` in its folder, back to the first one after the last if `wrap`.
+/// Without `last`, the first track of the first folder.
+/// A `last` gone from the library, e.g. deleted while playing, is placed by its file name.
+pub fn get_next_in_folder(
+    music_dir: &MusicDir,
+    index: &LibraryIndex,
+    last: Option<&Path>,
+    wrap: bool,
+) -> Option<PathBuf> {
+    let albums = music_dir.get_albums();
+    let album = last
+        .and_then(|last| find_album(&albums, last))
+        .or_else(|| albums.iter().copied().min_by(|a, b| compare_folders(a, b)))?;
+    get_next(&get_album_order(album, index), last, wrap)
+}
+
+/// Track after `last` with the whole tree in order, folder after folder.
+pub fn get_next_in_tree(
+    music_dir: &MusicDir,
+    index: &LibraryIndex,
+    last: Option<&Path>,
+    wrap: bool,
+) -> Option<PathBuf> {
+    get_next(&get_tree_order(music_dir, index), last, wrap)
+}
+
+/// Every track of the tree in order, folder after folder.
+pub fn get_tree_order<'a>(music_dir: &'a MusicDir, index: &LibraryIndex) -> Vec<&'a PathBuf> {
+    let mut albums = music_dir.get_albums();
+    albums.sort_by(|a, b| compare_folders(a, b));
+    albums
+        .into_iter()
+        .flat_map(|a| get_album_order(a, index))
+        .collect()
+}
+
+/// Track after `last` in its folder, none after the last one.
+pub fn get_next_in_album(
+    music_dir: &MusicDir,
+    index: &LibraryIndex,
+    last: &Path,
+) -> Option<PathBuf> {
+    let albums = music_dir.get_albums();
+    let album = find_album(&albums, last)?;
+    get_next(&get_album_order(album, index), Some(last), false)
+}
+
+/// Last track of the folder of `path`.
+pub fn get_last_in_album(
+    music_dir: &MusicDir,
+    index: &LibraryIndex,
+    path: &Path,
+) -> Option<PathBuf> {
+    let albums = music_dir.get_albums();
+    let album = find_album(&albums, path)?;
+    get_album_order(album, index).last().map(|p| (*p).clone())
+}
+
+/// First track of a random folder, avoiding the `avoided` folders if any other is left.
+pub fn get_random_album_start(
+    music_dir: &MusicDir,
+    index: &LibraryIndex,
+    avoided: &HashSet<PathBuf>,
+    rng: &mut impl Rng,
+) -> Option<PathBuf> {
+    let albums = music_dir.get_albums();
+    let allowed: Vec<&[PathBuf]> = albums
+        .iter()
+        .copied()
+        .filter(|a| !avoided.contains(&get_album_folder(&a[0])))
+        .collect();
+    let candidates = if allowed.is_empty() {
+        &albums
+    } else {
+        &allowed
+    };
+    let album = candidates.choose(rng)?;
+    get_album_order(album, index).first().map(|p| (*p).clone())
+}
+
+/// The folder a track belongs to as part of an album.
+pub fn get_album_folder(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+// the album of the folder of `path`, even if `path` itself is no longer in it
+fn find_album<'a>(albums: &[&'a [PathBuf]], path: &Path) -> Option<&'a [PathBuf]> {
+    albums
+        .iter()
+        .copied()
+        .find(|a| a.first().is_some_and(|p| p.parent() == path.parent()))
+}
+
+fn get_next(order: &[&PathBuf], last: Option<&Path>, wrap: bool) -> Option<PathBuf> {
+    let next = match last {
+        Some(last) => match order.iter().position(|p| *p == last) {
+            Some(i) => i + 1,
+            // gone from the library: the first track that came after it
+            None => order
+                .iter()
+                .position(|p| compare_paths(p, last) == Ordering::Greater)
+                .unwrap_or(order.len()),
+        },
+        None => 0,
+    };
+    match order.get(next) {
+        Some(p) => Some((*p).clone()),
+        None if wrap => order.first().map(|p| (*p).clone()),
+        None => None,
+    }
+}
+
+/// Disc and track number order, tracks without a number after in natural file name order.
+fn get_album_order<'a>(album: &'a [PathBuf], index: &LibraryIndex) -> Vec<&'a PathBuf> {
+    let mut tracks: Vec<&PathBuf> = album.iter().collect();
+    tracks.sort_by_cached_key(|p| {
+        let entry = index.get(p);
+        let track = entry.and_then(|e| e.track_number);
+        let disc = entry.and_then(|e| e.disc_number).unwrap_or(1);
+        (track.is_none(), disc, track, NaturalName(get_name(p)))
+    });
+    tracks
+}
+
+fn compare_folders(a: &[PathBuf], b: &[PathBuf]) -> Ordering {
+    let folder = |album: &[PathBuf]| {
+        album
+            .first()
+            .and_then(|p| p.parent())
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    natord::compare(&folder(a), &folder(b))
+}
+
+// folder, then file name, in natural order
+fn compare_paths(a: &Path, b: &Path) -> Ordering {
+    let folder = |p: &Path| {
+        p.parent()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    natord::compare(&folder(a), &folder(b))
+        .then_with(|| NaturalName(get_name(a)).cmp(&NaturalName(get_name(b))))
+}
+
+fn get_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// "2 - b" before "10 - a"
+#[derive(Eq, PartialEq)]
+struct NaturalName(String);
+
+impl Ord for NaturalName {
+    fn cmp(&self, other: &Self) -> Ordering {
+        natord::compare(&self.0, &other.0)
+    }
+}
+
+impl PartialOrd for NaturalName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    use crate::backend::library_index::IndexEntry;
+
+    // /music/<folder>/<track>, with its track number if any
+    struct Library {
+        music_dir: MusicDir,
+        index: LibraryIndex,
+    }
+
+    impl Library {
+        fn new(tracks: &[(&str, Option<u32>)]) -> Self {
+            let mut index = LibraryIndex::default();
+            let mut paths = vec![];
+            for &(path, track_number) in tracks {
+                let path = PathBuf::from(format!("/music/{path}"));
+                index.insert(IndexEntry {
+                    path: path.clone(),
+                    modified: SystemTime::UNIX_EPOCH,
+                    size: 0,
+                    format: None,
+                    name: None,
+                    artist: None,
+                    album: None,
+                    track_number,
+                    disc_number: None,
+                    duration: None,
+                    content_hash: None,
+                    fingerprint: None,
+                });
+                paths.push(path);
+            }
+            Self {
+                music_dir: MusicDir::from_track_paths(Path::new("/music"), paths).unwrap(),
+                index,
+            }
+        }
+
+        // the tracks in order from `first`, relative to /music, until the order ends
+        // or `max` tracks
+        fn play(
+            &self,
+            get_next: impl Fn(&MusicDir, &LibraryIndex, Option<&Path>) -> Option<PathBuf>,
+            first: Option<&str>,
+            max: usize,
+        ) -> Vec<String> {
+            let mut last = first.map(|p| PathBuf::from(format!("/music/{p}")));
+            let mut played = vec![];
+            while played.len() < max {
+                let Some(next) = get_next(&self.music_dir, &self.index, last.as_deref()) else {
+                    break;
+                };
+                played.push(next.strip_prefix("/music").unwrap().display().to_string());
+                last = Some(next);
+            }
+            played
+        }
+    }
+
+    fn in_folder(
+        wrap: bool,
+    ) -> impl Fn(&MusicDir, &LibraryIndex, Option<&Path>) -> Option<PathBuf> {
+        move |md, index, last| get_next_in_folder(md, index, last, wrap)
+    }
+
+    fn in_tree(wrap: bool) -> impl Fn(&MusicDir, &LibraryIndex, Option<&Path>) -> Option<PathBuf> {
+        move |md, index, last| get_next_in_tree(md, index, last, wrap)
+    }
+
+    fn make_library() -> Library {
+        Library::new(&[
+            ("b/10.mp3", None),
+            ("b/2.mp3", None),
+            ("a/intro.mp3", None),
+            ("a/z.mp3", Some(1)),
+            ("a/y.mp3", Some(2)),
+            ("a 10/1.mp3", None),
+            ("a 2/1.mp3", None),
+        ])
+    }
+
+    #[test]
+    fn folder_order_plays_its_folder_by_track_number_then_name() {
+        let library = make_library();
+        assert_eq!(
+            library.play(in_folder(false), None, 10),
+            ["a/z.mp3", "a/y.mp3", "a/intro.mp3"]
+        );
+        assert_eq!(
+            library.play(in_folder(false), Some("b/2.mp3"), 10),
+            ["b/10.mp3"]
+        );
+    }
+
+    #[test]
+    fn library_order_goes_folder_after_folder() {
+        let library = make_library();
+        assert_eq!(
+            library.play(in_tree(false), None, 10),
+            [
+                "a/z.mp3",
+                "a/y.mp3",
+                "a/intro.mp3",
+                "a 2/1.mp3",
+                "a 10/1.mp3",
+                "b/2.mp3",
+                "b/10.mp3"
+            ]
+        );
+    }
+
+    #[test]
+    fn repeat_all_starts_over_after_the_last_track() {
+        let library = make_library();
+        assert_eq!(
+            library.play(in_folder(true), Some("b/2.mp3"), 3),
+            ["b/10.mp3", "b/2.mp3", "b/10.mp3"]
+        );
+        assert_eq!(
+            library.play(in_tree(true), Some("a 10/1.mp3"), 4),
+            ["b/2.mp3", "b/10.mp3", "a/z.mp3", "a/y.mp3"]
+        );
+        assert!(library.play(in_tree(false), Some("b/10.mp3"), 4).is_empty());
+    }
+
+    #[test]
+    fn the_order_goes_on_after_a_removed_track() {
+        // 02.mp3 and the whole "c" folder were deleted while 02.mp3 played
+        let library = Library::new(&[
+            ("b/01.mp3", Some(1)),
+            ("b/03.mp3", Some(3)),
+            ("b/04.mp3", Some(4)),
+            ("d/01.mp3", Some(1)),
+        ]);
+        assert_eq!(
+            library.play(in_folder(false), Some("b/02.mp3"), 10),
+            ["b/03.mp3", "b/04.mp3"]
+        );
+        assert_eq!(
+            library.play(in_tree(false), Some("b/02.mp3"), 10),
+            ["b/03.mp3", "b/04.mp3", "d/01.mp3"]
+        );
+        assert_eq!(
+            library.play(in_tree(false), Some("c/01.mp3"), 10),
+            ["d/01.mp3"]
+        );
+        assert_eq!(
+            library.play(in_tree(true), Some("e/01.mp3"), 1),
+            ["b/01.mp3"]
+        );
+        // the last track of a folder
+        assert!(library
+            .play(in_folder(false), Some("b/05.mp3"), 10)
+            .is_empty());
+        assert_eq!(
+            library.play(in_folder(true), Some("b/05.mp3"), 1),
+            ["b/01.mp3"]
+        );
+        let last = Path::new("/music/b/05.mp3");
+        assert_eq!(
+            get_next_in_album(&library.music_dir, &library.index, last),
+            None
+        );
+        let removed = Path::new("/music/b/02.mp3");
+        assert_eq!(
+            get_next_in_album(&library.music_dir, &library.index, removed),
+            Some(PathBuf::from("/music/b/03.mp3"))
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::SESSION_RELATIVE_PATH;
+
+/// What was playing when the app was closed, restored on the next launch.
+/// The play order and the repeat mode are kept in the settings.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub current: Option<PathBuf>,
+    pub position: Duration, // in the current track
+    pub paused: bool,
+    pub upcoming: Vec<PathBuf>,
+    #[serde(default)]
+    pub explicit: Vec<bool>, // for each upcoming track, whether the user asked for it
+}
+
+impl Session {
+    pub fn is_empty(&self) -> bool {
+        self.current.is_none() && self.upcoming.is_empty()
+    }
+}
+
+pub fn read(dir: &Path) -> Session {
+    let path = dir.join(SESSION_RELATIVE_PATH);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            return Session::default();
+        }
+    };
+    serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+        eprintln!("Error in parsing {}: {e}", path.display());
+        Session::default()
+    })
+}
+
+/// Losing the session only starts the next launch from scratch, so failing to write it is not fatal.
+pub fn write(dir: &Path, session: &Session) {
+    let path = dir.join(SESSION_RELATIVE_PATH);
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create file '{}': {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = serde_json::to_writer(BufWriter::new(file), session) {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
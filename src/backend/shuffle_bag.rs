@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::SHUFFLE_BAG_RELATIVE_PATH;
+
+/// A permutation of the library played in order, reshuffled once every track was played.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ShuffleBag {
+    order: Vec<PathBuf>,
+    position: usize, // tracks before it were already played in this round
+}
+
+impl ShuffleBag {
+    /// Merges library changes: removed tracks leave the bag,
+    /// new ones go to random places among the tracks not played yet.
+    pub fn sync(&mut self, tracks: &[&PathBuf], rng: &mut impl Rng) {
+        let in_library: HashSet<&PathBuf> = tracks.iter().copied().collect();
+        let played_removed = self.order[..self.position]
+            .iter()
+            .filter(|p| !in_library.contains(p))
+            .count();
+        self.position -= played_removed;
+        self.order.retain(|p| in_library.contains(p));
+
+        let in_bag: HashSet<PathBuf> = self.order.iter().cloned().collect();
+        for &track in tracks {
+            if !in_bag.contains(track) {
+                let i = rng.gen_range(self.position..=self.order.len());
+                self.order.insert(i, track.clone());
+            }
+        }
+    }
+
+    pub fn next(&mut self, rng: &mut impl Rng) -> Option<PathBuf> {
+        if self.order.is_empty() {
+            return None;
+        }
+        if self.position >= self.order.len() {
+            self.reshuffle(rng);
+        }
+        self.position += 1;
+        Some(self.order[self.position - 1].clone())
+    }
+
+    fn reshuffle(&mut self, rng: &mut impl Rng) {
+        let last = self.order.last().cloned();
+        self.order.shuffle(rng);
+        // don't play the same track twice in a row across rounds
+        if self.order.len() > 1 && self.order.first() == last.as_ref() {
+            let n = self.order.len() - 1;
+            self.order.swap(0, n);
+        }
+        self.position = 0;
+        println!("[BAG] reshuffled {} tracks", self.order.len());
+    }
+}
+
+pub fn read(dir: &Path) -> ShuffleBag {
+    let path = dir.join(SHUFFLE_BAG_RELATIVE_PATH);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            return ShuffleBag::default();
+        }
+    };
+    match serde_json::from_reader::<_, ShuffleBag>(BufReader::new(file)) {
+        Ok(mut bag) => {
+            bag.position = bag.position.min(bag.order.len());
+            bag
+        }
+        Err(e) => {
+            eprintln!("Error in parsing {}: {e}", path.display());
+            ShuffleBag::default()
+        }
+    }
+}
+
+/// Losing the bag only starts a new round, so failing to write it is not fatal.
+pub fn write(dir: &Path, bag: &ShuffleBag) {
+    let path = dir.join(SHUFFLE_BAG_RELATIVE_PATH);
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create file '{}': {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = serde_json::to_writer(BufWriter::new(file), bag) {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn make_tracks(names: impl IntoIterator<Item = usize>) -> Vec<PathBuf> {
+        names
+            .into_iter()
+            .map(|n| PathBuf::from(format!("/music/{n:02}.mp3")))
+            .collect()
+    }
+
+    fn make_bag(tracks: &[PathBuf], rng: &mut StdRng) -> ShuffleBag {
+        let mut bag = ShuffleBag::default();
+        bag.sync(&tracks.iter().collect::<Vec<_>>(), rng);
+        bag
+    }
+
+    fn take(n: usize, bag: &mut ShuffleBag, rng: &mut StdRng) -> Vec<PathBuf> {
+        (0..n).map(|_| bag.next(rng).unwrap()).collect()
+    }
+
+    fn sorted(mut tracks: Vec<PathBuf>) -> Vec<PathBuf> {
+        tracks.sort();
+        tracks
+    }
+
+    #[test]
+    fn every_track_plays_once_per_round() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let tracks = make_tracks(0..10);
+        let mut bag = make_bag(&tracks, &mut rng);
+        for _ in 0..5 {
+            assert_eq!(sorted(take(10, &mut bag, &mut rng)), tracks);
+        }
+    }
+
+    #[test]
+    fn no_track_plays_twice_in_a_row_across_rounds() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut bag = make_bag(&make_tracks(0..3), &mut rng);
+            let picks = take(300, &mut bag, &mut rng);
+            assert!(picks.windows(2).all(|w| w[0] != w[1]), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn a_single_track_plays_every_round() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut bag = make_bag(&make_tracks([0]), &mut rng);
+        assert_eq!(take(3, &mut bag, &mut rng), make_tracks([0, 0, 0]));
+        assert_eq!(ShuffleBag::default().next(&mut rng), None);
+    }
+
+    #[test]
+    fn sync_keeps_the_round_going_with_the_library_changes() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut bag = make_bag(&make_tracks(0..10), &mut rng);
+        let played = take(4, &mut bag, &mut rng);
+
+        // 2 played and 2 unplayed tracks removed, 3 new ones
+        let unplayed: Vec<PathBuf> = bag.order[4..].to_vec();
+        let removed = [&played[0], &played[1], &unplayed[0], &unplayed[1]];
+        let mut library: Vec<PathBuf> = make_tracks(0..10)
+            .into_iter()
+            .filter(|t| !removed.contains(&t))
+            .collect();
+        library.extend(make_tracks(10..13));
+        bag.sync(&library.iter().collect::<Vec<_>>(), &mut rng);
+
+        assert_eq!(bag.position, 2);
+        assert_eq!(bag.order[..2], played[2..]);
+        // the rest of the round: the unplayed tracks left and the new ones
+        let mut rest = unplayed[2..].to_vec();
+        rest.extend(make_tracks(10..13));
+        assert_eq!(sorted(take(7, &mut bag, &mut rng)), sorted(rest));
+        // then a new round of the whole library
+        assert_eq!(sorted(take(9, &mut bag, &mut rng)), sorted(library));
+    }
+}
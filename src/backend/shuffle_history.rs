@@ -0,0 +1,97 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::NoRepeatWindow;
+use crate::SHUFFLE_HISTORY_RELATIVE_PATH;
+
+/// The tracks and album folders picked by the shuffle lately, most recent last.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ShuffleHistory {
+    recent: VecDeque<PathBuf>,
+    #[serde(default)]
+    recent_albums: VecDeque<PathBuf>,
+}
+
+impl ShuffleHistory {
+    pub fn push(&mut self, path: PathBuf, window_len: usize) {
+        push(&mut self.recent, path, window_len);
+    }
+
+    pub fn push_album(&mut self, folder: PathBuf, window_len: usize) {
+        push(&mut self.recent_albums, folder, window_len);
+    }
+
+    /// The tracks the picker should avoid.
+    pub fn get_window(&self, window_len: usize) -> HashSet<PathBuf> {
+        get_window(&self.recent, window_len)
+    }
+
+    /// The last `len` picks, most recent last.
+    pub fn get_recent(&self, len: usize) -> impl Iterator<Item = &PathBuf> {
+        self.recent
+            .iter()
+            .skip(self.recent.len().saturating_sub(len))
+    }
+
+    /// The album folders the picker should avoid.
+    pub fn get_album_window(&self, window_len: usize) -> HashSet<PathBuf> {
+        get_window(&self.recent_albums, window_len)
+    }
+}
+
+fn push(recent: &mut VecDeque<PathBuf>, path: PathBuf, window_len: usize) {
+    recent.push_back(path);
+    while recent.len() > window_len {
+        recent.pop_front();
+    }
+}
+
+fn get_window(recent: &VecDeque<PathBuf>, window_len: usize) -> HashSet<PathBuf> {
+    recent.iter().rev().take(window_len).cloned().collect()
+}
+
+impl NoRepeatWindow {
+    /// Number of items in the window for a library of `count` tracks (or albums).
+    /// At least one is always left out of it.
+    pub fn get_len(self, count: usize) -> usize {
+        let len = match self {
+            NoRepeatWindow::Tracks(n) => n,
+            NoRepeatWindow::Percent(p) => count * usize::from(p.min(100)) / 100,
+        };
+        len.min(count.saturating_sub(1))
+    }
+}
+
+pub fn read(dir: &Path) -> ShuffleHistory {
+    let path = dir.join(SHUFFLE_HISTORY_RELATIVE_PATH);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            return ShuffleHistory::default();
+        }
+    };
+    serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+        eprintln!("Error in parsing {}: {e}", path.display());
+        ShuffleHistory::default()
+    })
+}
+
+/// Losing the history only means tracks may repeat sooner, so failing to write it is not fatal.
+pub fn write(dir: &Path, history: &ShuffleHistory) {
+    let path = dir.join(SHUFFLE_HISTORY_RELATIVE_PATH);
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create file '{}': {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = serde_json::to_writer(BufWriter::new(file), history) {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
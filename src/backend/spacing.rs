@@ -0,0 +1,244 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use rand::Rng;
+
+use crate::backend::music_dir::MusicDir;
+use crate::backend::sequential;
+use crate::settings::{ShuffleSpacing, ShuffleWeighting};
+
+/// Artists and album folders of the last picks, which the next random pick should differ from.
+pub struct Spacing {
+    spacing: ShuffleSpacing,
+    artists: VecDeque<Option<String>>, // most recent last, none for unknown artists
+    albums: VecDeque<PathBuf>,
+}
+
+impl Spacing {
+    pub fn new(spacing: ShuffleSpacing) -> Self {
+        Self {
+            spacing,
+            artists: VecDeque::new(),
+            albums: VecDeque::new(),
+        }
+    }
+
+    /// Number of past picks this needs to know about.
+    pub fn get_len(&self) -> usize {
+        self.spacing.artist.max(self.spacing.album)
+    }
+
+    pub fn push(&mut self, artist: Option<&str>, album: &Path) {
+        self.artists.push_back(artist.map(normalize));
+        while self.artists.len() > self.spacing.artist {
+            self.artists.pop_front();
+        }
+        self.albums.push_back(album.to_path_buf());
+        while self.albums.len() > self.spacing.album {
+            self.albums.pop_front();
+        }
+    }
+
+    /// Whether a track by `artist` from the `album` folder is far enough from the last picks.
+    /// Tracks without an artist tag are only kept apart by their folder.
+    pub fn allows(&self, artist: Option<&str>, album: &Path) -> bool {
+        if self.albums.iter().any(|a| a == album) {
+            return false;
+        }
+        match artist.map(normalize) {
+            None => true,
+            Some(artist) => !self.artists.iter().flatten().any(|a| *a == artist),
+        }
+    }
+
+    /// Random track of `music_dir` not in `avoided` and far enough from the last picks.
+    /// Only when no track is, e.g. in a library of a single artist, the spacing is left out.
+    pub fn pick<'a>(
+        &self,
+        music_dir: &MusicDir,
+        weighting: ShuffleWeighting,
+        rng: &mut impl Rng,
+        avoided: &HashSet<PathBuf>,
+        get_artist: impl Fn(&Path) -> Option<&'a str>,
+    ) -> Option<PathBuf> {
+        let spaced = music_dir.get_random_track_path_where(weighting, rng, |path| {
+            !avoided.contains(path)
+                && self.allows(get_artist(path), &sequential::get_album_folder(path))
+        });
+        if spaced.is_some() {
+            return spaced;
+        }
+        println!("[SPACING] no track far enough from the last picks, picking without spacing");
+        music_dir.get_random_track_path_avoiding(weighting, rng, avoided)
+    }
+}
+
+fn normalize(artist: &str) -> String {
+    artist.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const SPACING: ShuffleSpacing = ShuffleSpacing {
+        artist: 3,
+        album: 5,
+    };
+
+    // /music/<artist>/<album>/<track>
+    struct Library {
+        music_dir: MusicDir,
+        artists: HashMap<PathBuf, String>,
+    }
+
+    impl Library {
+        fn new(albums: &[(&str, &str, usize)]) -> Self {
+            let mut paths = vec![];
+            let mut artists = HashMap::new();
+            for &(artist, album, tracks) in albums {
+                for i in 0..tracks {
+                    let path = PathBuf::from(format!("/music/{artist}/{album}/{i}.mp3"));
+                    artists.insert(path.clone(), artist.to_string());
+                    paths.push(path);
+                }
+            }
+            Self {
+                music_dir: MusicDir::from_track_paths(Path::new("/music"), paths).unwrap(),
+                artists,
+            }
+        }
+
+        fn get_artist(&self, path: &Path) -> Option<&str> {
+            self.artists.get(path).map(String::as_str)
+        }
+
+        // picks like the shuffle does, avoiding the last `window_len` picks
+        fn simulate(&self, picks: usize, window_len: usize, seed: u64) -> Vec<PathBuf> {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut spacing = Spacing::new(SPACING);
+            let mut picked: Vec<PathBuf> = vec![];
+            for _ in 0..picks {
+                let avoided = picked.iter().rev().take(window_len).cloned().collect();
+                let path = spacing
+                    .pick(
+                        &self.music_dir,
+                        ShuffleWeighting::Track,
+                        &mut rng,
+                        &avoided,
+                        |p| self.get_artist(p),
+                    )
+                    .unwrap();
+                spacing.push(self.get_artist(&path), &sequential::get_album_folder(&path));
+                picked.push(path);
+            }
+            picked
+        }
+
+        fn check_spacing(&self, picked: &[PathBuf]) {
+            for (i, path) in picked.iter().enumerate() {
+                let artist = self.get_artist(path);
+                let album = path.parent();
+                let recent = |n: usize| &picked[i.saturating_sub(n)..i];
+                assert!(
+                    !recent(SPACING.artist)
+                        .iter()
+                        .any(|p| self.get_artist(p) == artist),
+                    "pick {i}: {path:?} by the artist of one of {:?}",
+                    recent(SPACING.artist)
+                );
+                assert!(
+                    !recent(SPACING.album).iter().any(|p| p.parent() == album),
+                    "pick {i}: {path:?} from the album of one of {:?}",
+                    recent(SPACING.album)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn allows_what_is_outside_the_window() {
+        let mut spacing = Spacing::new(ShuffleSpacing {
+            artist: 2,
+            album: 3,
+        });
+        let album = |name: &str| PathBuf::from(format!("/music/{name}"));
+        spacing.push(Some("The Band"), &album("a"));
+        assert!(!spacing.allows(Some("  the band "), &album("b")));
+        assert!(!spacing.allows(Some("Other"), &album("a")));
+        assert!(spacing.allows(Some("Other"), &album("b")));
+
+        spacing.push(Some("Other"), &album("b"));
+        spacing.push(None, &album("c"));
+        // the band is 3 picks back, its folder too
+        assert!(spacing.allows(Some("The Band"), &album("d")));
+        assert!(!spacing.allows(Some("The Band"), &album("a")));
+        assert!(!spacing.allows(Some("Other"), &album("d")));
+        // unknown artists are only kept apart by their folder
+        assert!(spacing.allows(None, &album("d")));
+        assert!(!spacing.allows(None, &album("c")));
+
+        spacing.push(None, &album("d"));
+        assert!(spacing.allows(Some("The Band"), &album("a")));
+    }
+
+    #[test]
+    fn zero_spacing_allows_everything() {
+        let mut spacing = Spacing::new(ShuffleSpacing {
+            artist: 0,
+            album: 0,
+        });
+        assert_eq!(spacing.get_len(), 0);
+        spacing.push(Some("a"), Path::new("/music/a"));
+        assert!(spacing.allows(Some("a"), Path::new("/music/a")));
+    }
+
+    #[test]
+    fn long_session_keeps_the_spacing() {
+        let albums: Vec<_> = (0..12)
+            .map(|i| {
+                (
+                    ["a", "b", "c", "d", "e", "f"][i % 6],
+                    ["x", "y"][i / 6],
+                    10 + i,
+                )
+            })
+            .collect();
+        let library = Library::new(&albums);
+        let picked = library.simulate(20_000, 20, 1);
+        library.check_spacing(&picked);
+        // the no-repeat window holds too
+        for (i, path) in picked.iter().enumerate() {
+            assert!(!picked[i.saturating_sub(20)..i].contains(path));
+        }
+    }
+
+    #[test]
+    fn spacing_holds_when_random_picks_rarely_allow_it() {
+        // a random pick lands on one of the small artists once in 250, so the 100 random
+        // attempts often fail after a pick of the big artist
+        let mut albums: Vec<_> = (0..4)
+            .map(|i| ("big", ["1", "2", "3", "4"][i], 500))
+            .collect();
+        for artist in ["s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8"] {
+            albums.push((artist, "only", 1));
+        }
+        let library = Library::new(&albums);
+        let picked = library.simulate(1_000, 0, 2);
+        library.check_spacing(&picked);
+    }
+
+    #[test]
+    fn spacing_is_left_out_only_when_no_track_allows_it() {
+        // a single artist and album: every pick breaks the spacing, none breaks the window
+        let library = Library::new(&[("solo", "album", 10)]);
+        let picked = library.simulate(1_000, 5, 3);
+        for (i, path) in picked.iter().enumerate() {
+            assert!(!picked[i.saturating_sub(5)..i].contains(path));
+        }
+    }
+}
@@ -0,0 +1,636 @@
+use std::f32::consts::FRAC_PI_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+use rodio::source::{SeekError, UniformSourceIterator};
+use rodio::Source;
+
+use crate::backend::track_source::TrackSource;
+
+/// What a chain did, sent from the audio thread.
+pub enum ChainEvent {
+    Switched(u64), // the chain with this id went on with its armed track
+    Ended(u64),    // the chain with this id is gone: out of tracks, skipped or cleared
+}
+
+/// Tracks played back to back as a single source: when a track ends, the armed one goes on
+/// from the very next sample, or fades in over the end of the current one when armed with a
+/// crossfade. Every track is converted to the format of the output, so the stream keeps one
+/// sample rate and channel count across tracks.
+pub struct TrackChain {
+    id: u64,
+    current: Option<UniformSourceIterator<TrackSource>>, // none while fading out to silence
+    current_len: Option<u64>, // in samples, when the duration of the current track is known
+    fade_in: Option<FadeIn>,  // of the current track
+    outgoing: Vec<Outgoing>,  // previous tracks still fading out
+    shared: Arc<Shared>,
+    channels: u16,
+    sample_rate: u32,
+    event_sender: Sender<ChainEvent>,
+}
+
+// a previous track, fading out from `gain` to silence
+struct Outgoing {
+    source: UniformSourceIterator<TrackSource>,
+    fade: Fade,
+    gain: f32,
+    ended: bool,
+}
+
+// the current track, fading in from `gain` to full volume
+struct FadeIn {
+    fade: Fade,
+    gain: f32,
+}
+
+// progress of a fade, from 0 to a quarter turn for equal power gains
+#[derive(Clone, Copy)]
+struct Fade {
+    len: u64, // in samples
+    done: u64,
+}
+
+// a seek during a crossfade fades the other track out over this, instead of cutting it
+const SEEK_FADE: Duration = Duration::from_millis(20);
+
+struct Shared {
+    next: Mutex<Option<(TrackSource, u64)>>, // with its crossfade in samples, 0 for none
+    next_fade: AtomicU64,                    // the same crossfade, read without the lock
+    skip: AtomicU64,                         // fade of a skip not done yet, or NO_SKIP
+    played: AtomicU64,                       // samples of the current track
+}
+
+const NO_SKIP: u64 = u64::MAX;
+
+/// The side of a chain the player keeps once the chain is in the sink.
+pub struct ChainHandle {
+    pub id: u64,
+    shared: Arc<Shared>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl TrackChain {
+    pub fn new(
+        id: u64,
+        first: TrackSource,
+        channels: u16,
+        sample_rate: u32,
+        event_sender: Sender<ChainEvent>,
+    ) -> (Self, ChainHandle) {
+        let shared = Arc::new(Shared {
+            next: Mutex::new(None),
+            next_fade: AtomicU64::new(0),
+            skip: AtomicU64::new(NO_SKIP),
+            played: AtomicU64::new(0),
+        });
+        let handle = ChainHandle {
+            id,
+            shared: shared.clone(),
+            channels,
+            sample_rate,
+        };
+        let current_len = first
+            .total_duration()
+            .map(|d| to_samples(d, channels, sample_rate));
+        let chain = Self {
+            id,
+            current: Some(UniformSourceIterator::new(first, channels, sample_rate)),
+            current_len,
+            fade_in: None,
+            outgoing: vec![],
+            shared,
+            channels,
+            sample_rate,
+            event_sender,
+        };
+        (chain, handle)
+    }
+
+    // the audio thread doesn't wait for the player: a busy lock is like nothing armed,
+    // the chain then ends and the player plays the armed track in a new chain
+    fn take_armed(&self) -> Option<(TrackSource, u64)> {
+        let armed = self.shared.next.try_lock().ok()?.take()?;
+        self.shared.next_fade.store(0, Ordering::Relaxed);
+        Some(armed)
+    }
+
+    // makes `next` the current track, the previous one fading out over `fade` samples
+    fn switch(&mut self, next: TrackSource, fade: u64) {
+        self.current_len = next
+            .total_duration()
+            .map(|d| to_samples(d, self.channels, self.sample_rate));
+        let next = UniformSourceIterator::new(next, self.channels, self.sample_rate);
+        let previous = self.current.replace(next);
+        self.fade_out(previous, fade);
+        self.fade_in = (fade > 0).then_some(FadeIn {
+            fade: Fade::new(fade),
+            gain: 0.0,
+        });
+        self.shared.played.store(0, Ordering::Relaxed);
+        self.event_sender
+            .send(ChainEvent::Switched(self.id))
+            .unwrap();
+    }
+
+    // the current track fades out from where its fade in got to
+    fn fade_out(&mut self, previous: Option<UniformSourceIterator<TrackSource>>, fade: u64) {
+        let gain = self.fade_in.take().map_or(1.0, |f| f.get_gain());
+        if let Some(source) = previous.filter(|_| fade > 0) {
+            self.outgoing.push(Outgoing {
+                source,
+                fade: Fade::new(fade),
+                gain,
+                ended: false,
+            });
+        }
+    }
+
+    fn skip(&mut self, fade: u64) {
+        // the running fades end along with the skip fade, from the gain they got to
+        self.shorten_fades(fade);
+        match self.take_armed() {
+            Some((next, _)) => self.switch(next, fade),
+            // fades out, then the chain ends
+            None => {
+                let previous = self.current.take();
+                self.fade_out(previous, fade);
+            }
+        }
+    }
+
+    fn shorten_fades(&mut self, fade: u64) {
+        for outgoing in &mut self.outgoing {
+            outgoing.gain = outgoing.get_gain();
+            outgoing.fade = outgoing.fade.shorten(fade);
+        }
+        self.outgoing.retain(|o| o.fade.len > 0);
+        if let Some(fade_in) = &mut self.fade_in {
+            fade_in.gain = fade_in.get_gain();
+            fade_in.fade = fade_in.fade.shorten(fade);
+        }
+        self.fade_in.take_if(|f| f.fade.len == 0);
+    }
+
+    // whether the end of the current track is near enough for the armed one to fade in
+    fn is_crossfade_due(&self) -> bool {
+        let fade = self.shared.next_fade.load(Ordering::Relaxed);
+        fade > 0 && self.outgoing.is_empty() && self.get_current_left().is_some_and(|l| l <= fade)
+    }
+
+    fn get_current_left(&self) -> Option<u64> {
+        let played = self.shared.played.load(Ordering::Relaxed);
+        Some(self.current_len?.saturating_sub(played))
+    }
+
+    // adds the outgoing tracks to a sample of the current one, with equal power gains
+    fn mix(&mut self, sample: f32) -> f32 {
+        let mut mixed = match &mut self.fade_in {
+            Some(fade_in) => {
+                let gain = fade_in.get_gain();
+                fade_in.fade.done += 1;
+                sample * gain
+            }
+            None => sample,
+        };
+        self.fade_in.take_if(|f| f.fade.is_done());
+        for outgoing in &mut self.outgoing {
+            let gain = outgoing.get_gain();
+            outgoing.fade.done += 1;
+            match outgoing.source.next() {
+                Some(old) => mixed += old * gain,
+                None => outgoing.ended = true,
+            }
+        }
+        self.outgoing.retain(|o| !o.ended && !o.fade.is_done());
+        mixed
+    }
+}
+
+impl Fade {
+    fn new(len: u64) -> Self {
+        Self { len, done: 0 }
+    }
+
+    fn get_angle(self) -> f32 {
+        self.done as f32 / self.len as f32 * FRAC_PI_2
+    }
+
+    fn is_done(self) -> bool {
+        self.done >= self.len
+    }
+
+    // what is left of the fade, at most `len` samples, from the start
+    fn shorten(self, len: u64) -> Self {
+        Self::new((self.len - self.done).min(len))
+    }
+}
+
+impl Outgoing {
+    fn get_gain(&self) -> f32 {
+        self.gain * self.fade.get_angle().cos()
+    }
+}
+
+impl FadeIn {
+    fn get_gain(&self) -> f32 {
+        self.gain + (1.0 - self.gain) * self.fade.get_angle().sin()
+    }
+}
+
+impl ChainHandle {
+    /// The track following the current one, replacing the one armed before if any.
+    /// It fades in over the last `crossfade` of the current track, zero for none.
+    pub fn arm(&self, source: TrackSource, crossfade: Duration) {
+        let fade = self.to_samples(crossfade);
+        let mut next = self.shared.next.lock().unwrap();
+        *next = Some((source, fade));
+        self.shared.next_fade.store(fade, Ordering::Relaxed);
+    }
+
+    /// Takes the armed track back, none if the chain already went on with it.
+    pub fn disarm(&self) -> Option<TrackSource> {
+        let mut next = self.shared.next.lock().unwrap();
+        self.shared.next_fade.store(0, Ordering::Relaxed);
+        next.take().map(|(source, _)| source)
+    }
+
+    /// Goes on with the armed track, or ends the chain if there is none, fading over `fade`.
+    pub fn skip(&self, fade: Duration) {
+        self.shared
+            .skip
+            .store(self.to_samples(fade), Ordering::Relaxed);
+    }
+
+    /// Position in the current track. While two tracks overlap, the one fading in is current.
+    pub fn get_pos(&self) -> Duration {
+        let frames = self.shared.played.load(Ordering::Relaxed) / u64::from(self.channels);
+        Duration::from_secs_f64(frames as f64 / f64::from(self.sample_rate))
+    }
+
+    fn to_samples(&self, duration: Duration) -> u64 {
+        to_samples(duration, self.channels, self.sample_rate)
+    }
+}
+
+impl Iterator for TrackChain {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.shared.skip.load(Ordering::Relaxed) != NO_SKIP {
+            let fade = self.shared.skip.swap(NO_SKIP, Ordering::Relaxed);
+            self.skip(fade);
+        }
+        if self.is_crossfade_due() {
+            if let Some((next, _)) = self.take_armed() {
+                // ends with the current track, even if it was armed late
+                let fade = self.get_current_left().unwrap_or(0);
+                self.switch(next, fade);
+            }
+        }
+        loop {
+            let Some(current) = &mut self.current else {
+                // fading out with nothing to follow
+                if self.outgoing.is_empty() {
+                    return None;
+                }
+                return Some(self.mix(0.0));
+            };
+            if let Some(sample) = current.next() {
+                self.shared.played.fetch_add(1, Ordering::Relaxed);
+                return Some(self.mix(sample));
+            }
+            // the armed track goes on from the very next sample
+            match self.take_armed() {
+                Some((next, _)) => self.switch(next, 0),
+                None => self.current = None,
+            }
+        }
+    }
+}
+
+impl Source for TrackChain {
+    fn current_span_len(&self) -> Option<usize> {
+        None // one format all along
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        // the seek is heard at once, the running fades end soon after
+        self.shorten_fades(to_samples(SEEK_FADE, self.channels, self.sample_rate));
+        if let Some(current) = &mut self.current {
+            current.try_seek(pos)?;
+        }
+        self.shared.played.store(
+            to_samples(pos, self.channels, self.sample_rate),
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+}
+
+impl Drop for TrackChain {
+    fn drop(&mut self) {
+        // the player is gone when the app closes
+        let _ = self.event_sender.send(ChainEvent::Ended(self.id));
+    }
+}
+
+fn to_samples(duration: Duration, channels: u16, sample_rate: u32) -> u64 {
+    let frames = (duration.as_secs_f64() * f64::from(sample_rate)).round() as u64;
+    frames * u64::from(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_4;
+
+    use crossbeam_channel::{unbounded, Receiver};
+
+    use crate::backend::track_source::tests::write_wav;
+
+    const RATE: u32 = 44_100;
+
+    // a mono track holding the same value all along
+    fn make_track(name: &str, value: f32, millis: u32) -> TrackSource {
+        let samples = vec![value; (RATE * millis / 1000) as usize];
+        TrackSource::open(&write_wav(name, 1, RATE, &samples)).unwrap()
+    }
+
+    fn make_chain(first: TrackSource) -> (TrackChain, ChainHandle, Receiver<ChainEvent>) {
+        let (event_sender, events) = unbounded();
+        let (chain, handle) = TrackChain::new(1, first, 1, RATE, event_sender);
+        (chain, handle, events)
+    }
+
+    // the samples of the chain, and after how many of them it switched
+    fn play(chain: &mut TrackChain, events: &Receiver<ChainEvent>) -> (Vec<f32>, Vec<usize>) {
+        let mut samples = vec![];
+        let mut switches = vec![];
+        for sample in chain.by_ref() {
+            samples.push(sample);
+            for event in events.try_iter() {
+                match event {
+                    ChainEvent::Switched(1) => switches.push(samples.len() - 1),
+                    ChainEvent::Switched(_) | ChainEvent::Ended(_) => {
+                        panic!("unexpected event")
+                    }
+                }
+            }
+        }
+        (samples, switches)
+    }
+
+    fn assert_close(sample: f32, expected: f32) {
+        assert!((sample - expected).abs() < 1e-3, "{sample} != {expected}");
+    }
+
+    #[test]
+    fn armed_track_starts_on_the_next_sample() {
+        let (mut chain, handle, events) = make_chain(make_track("gapless_a.wav", 0.25, 1000));
+        handle.arm(make_track("gapless_b.wav", 0.5, 500), Duration::ZERO);
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert_eq!(samples.len(), 44_100 + 22_050);
+        // the first sample of the armed track is the one read when the switch is told
+        assert_eq!(switches, vec![44_100]);
+        samples[..44_100]
+            .iter()
+            .for_each(|&s| assert_close(s, 0.25));
+        samples[44_100..].iter().for_each(|&s| assert_close(s, 0.5));
+    }
+
+    #[test]
+    fn position_follows_the_current_track() {
+        let (mut chain, handle, _events) = make_chain(make_track("pos_a.wav", 0.25, 500));
+        handle.arm(make_track("pos_b.wav", 0.5, 500), Duration::ZERO);
+        for _ in 0..11_025 {
+            chain.next();
+        }
+        assert_eq!(handle.get_pos(), Duration::from_millis(250));
+        for _ in 0..22_050 {
+            chain.next();
+        }
+        assert_eq!(handle.get_pos(), Duration::from_millis(250));
+
+        chain.try_seek(Duration::from_millis(100)).unwrap();
+        assert_eq!(handle.get_pos(), Duration::from_millis(100));
+        assert_close(chain.next().unwrap(), 0.5);
+    }
+
+    #[test]
+    fn ends_with_its_tracks_and_tells_it_when_dropped() {
+        let (mut chain, _handle, events) = make_chain(make_track("end_a.wav", 0.25, 100));
+        let (samples, switches) = play(&mut chain, &events);
+        assert_eq!(samples.len(), 4_410);
+        assert!(switches.is_empty());
+        assert_eq!(chain.next(), None);
+
+        drop(chain);
+        assert!(matches!(events.try_recv(), Ok(ChainEvent::Ended(1))));
+    }
+
+    #[test]
+    fn disarmed_track_is_given_back() {
+        let (mut chain, handle, events) = make_chain(make_track("disarm_a.wav", 0.25, 100));
+        handle.arm(make_track("disarm_b.wav", 0.5, 100), Duration::ZERO);
+        assert!(handle.disarm().is_some());
+        assert!(handle.disarm().is_none());
+        let (samples, switches) = play(&mut chain, &events);
+        assert_eq!(samples.len(), 4_410);
+        assert!(switches.is_empty());
+    }
+
+    #[test]
+    fn armed_track_is_converted_to_the_chain_format() {
+        let (mut chain, handle, events) = make_chain(make_track("format_a.wav", 0.25, 100));
+        let stereo = vec![0.5; 2 * 48_000 / 10];
+        let path = write_wav("format_b.wav", 2, 48_000, &stereo);
+        handle.arm(TrackSource::open(&path).unwrap(), Duration::ZERO);
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert_eq!(switches, vec![4_410]);
+        // 100 ms of mono at 44.1 kHz, give or take the resampler
+        let converted = samples.len() - 4_410;
+        assert!(converted.abs_diff(4_410) < 20, "{converted}");
+        samples[4_420..8_800]
+            .iter()
+            .for_each(|&s| assert_close(s, 0.5));
+    }
+
+    fn take(chain: &mut TrackChain, n: usize) -> Vec<f32> {
+        chain.by_ref().take(n).collect()
+    }
+
+    #[test]
+    fn armed_track_fades_in_over_the_end_of_the_current_one() {
+        let (mut chain, handle, events) = make_chain(make_track("fade_a.wav", 0.25, 1000));
+        handle.arm(
+            make_track("fade_b.wav", 0.5, 500),
+            Duration::from_millis(200),
+        );
+        let (samples, switches) = play(&mut chain, &events);
+
+        // 200 ms of overlap
+        assert_eq!(switches, vec![35_280]);
+        assert_eq!(samples.len(), 35_280 + 22_050);
+        samples[..35_280]
+            .iter()
+            .for_each(|&s| assert_close(s, 0.25));
+        // equal power: sin and cos gains
+        assert_close(samples[35_280], 0.25);
+        assert_close(samples[35_280 + 4_410], 0.75 * FRAC_PI_4.sin());
+        assert_close(samples[35_280 + 8_819], 0.5);
+        samples[35_280 + 8_820..]
+            .iter()
+            .for_each(|&s| assert_close(s, 0.5));
+    }
+
+    #[test]
+    fn position_follows_the_track_fading_in() {
+        let (mut chain, handle, _events) = make_chain(make_track("fade_pos_a.wav", 0.25, 1000));
+        handle.arm(
+            make_track("fade_pos_b.wav", 0.5, 500),
+            Duration::from_millis(200),
+        );
+        take(&mut chain, 35_280);
+        assert_eq!(handle.get_pos(), Duration::from_millis(800));
+        take(&mut chain, 4_410);
+        assert_eq!(handle.get_pos(), Duration::from_millis(100));
+        take(&mut chain, 4_410);
+        assert_eq!(handle.get_pos(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn track_armed_late_fades_in_over_what_is_left() {
+        let (mut chain, handle, events) = make_chain(make_track("late_a.wav", 0.25, 1000));
+        take(&mut chain, 40_000);
+        handle.arm(
+            make_track("late_b.wav", 0.5, 500),
+            Duration::from_millis(200),
+        );
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert_eq!(switches, vec![0]);
+        assert_eq!(samples.len(), 22_050);
+        assert_close(samples[0], 0.25);
+        assert_close(samples[4_099], 0.5);
+    }
+
+    #[test]
+    fn skip_fades_into_the_armed_track() {
+        let (mut chain, handle, events) = make_chain(make_track("skip_a.wav", 0.25, 1000));
+        handle.arm(make_track("skip_b.wav", 0.5, 500), Duration::ZERO);
+        take(&mut chain, 4_410);
+        handle.skip(Duration::from_millis(50));
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert_eq!(switches, vec![0]);
+        assert_eq!(samples.len(), 22_050);
+        assert_continuous(0.25, &samples);
+        samples[2_205..].iter().for_each(|&s| assert_close(s, 0.5));
+        assert_eq!(handle.get_pos(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn skip_with_nothing_armed_fades_out_and_ends() {
+        let (mut chain, handle, events) = make_chain(make_track("skip_end.wav", 0.25, 1000));
+        take(&mut chain, 4_410);
+        handle.skip(Duration::from_millis(50));
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert!(switches.is_empty());
+        assert_eq!(samples.len(), 2_205);
+        assert_continuous(0.25, &samples);
+        assert!(samples.windows(2).all(|w| w[1] <= w[0]));
+        assert!(samples[2_204] < 1e-3);
+
+        drop(chain);
+        assert!(matches!(events.try_recv(), Ok(ChainEvent::Ended(1))));
+    }
+
+    #[test]
+    fn skip_without_fade_ends_at_once() {
+        let (mut chain, handle, _events) = make_chain(make_track("skip_now.wav", 0.25, 1000));
+        take(&mut chain, 4_410);
+        handle.skip(Duration::ZERO);
+        assert_eq!(chain.next(), None);
+    }
+
+    // no step between two samples bigger than what the fades of these tracks make
+    fn assert_continuous(before: f32, samples: &[f32]) {
+        let mut previous = before;
+        for (i, &sample) in samples.iter().enumerate() {
+            assert!(
+                (sample - previous).abs() < 2e-3,
+                "step at {i}: {previous} -> {sample}"
+            );
+            previous = sample;
+        }
+    }
+
+    // in the middle of a 200 ms crossfade from a track at 0.25 to one at 0.5
+    fn make_crossfading_chain(name: &str) -> (TrackChain, ChainHandle, Receiver<ChainEvent>, f32) {
+        let a = make_track(&format!("{name}_a.wav"), 0.25, 1000);
+        let (mut chain, handle, events) = make_chain(a);
+        handle.arm(
+            make_track(&format!("{name}_b.wav"), 0.5, 500),
+            Duration::from_millis(200),
+        );
+        let last = *take(&mut chain, 35_280 + 4_410).last().unwrap();
+        assert_close(last, 0.75 * FRAC_PI_4.sin());
+        events.try_iter().for_each(drop);
+        (chain, handle, events, last)
+    }
+
+    #[test]
+    fn skip_during_a_crossfade_fades_both_tracks_out() {
+        let (mut chain, handle, events, last) = make_crossfading_chain("skip_fading");
+        handle.arm(make_track("skip_fading_c.wav", 0.75, 500), Duration::ZERO);
+        handle.skip(Duration::from_millis(50));
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert_eq!(switches, vec![0]);
+        assert_eq!(samples.len(), 22_050);
+        assert_continuous(last, &samples);
+        samples[2_205..].iter().for_each(|&s| assert_close(s, 0.75));
+    }
+
+    #[test]
+    fn skip_to_silence_during_a_crossfade_fades_both_tracks_out() {
+        let (mut chain, handle, events, last) = make_crossfading_chain("silence_fading");
+        handle.skip(Duration::from_millis(50));
+        let (samples, switches) = play(&mut chain, &events);
+
+        assert!(switches.is_empty());
+        assert_eq!(samples.len(), 2_205);
+        assert_continuous(last, &samples);
+        assert!(samples[2_204] < 1e-3);
+    }
+
+    #[test]
+    fn seek_during_a_crossfade_fades_the_previous_track_out() {
+        let (mut chain, handle, _events, last) = make_crossfading_chain("seek_fading");
+        chain.try_seek(Duration::from_millis(100)).unwrap();
+        assert_eq!(handle.get_pos(), Duration::from_millis(100));
+        let samples = take(&mut chain, 4_410);
+
+        assert_continuous(last, &samples);
+        // over SEEK_FADE
+        samples[882..].iter().for_each(|&s| assert_close(s, 0.5));
+    }
+}
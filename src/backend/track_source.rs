@@ -0,0 +1,328 @@
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::Source;
+use symphonia::core::audio::{Channels, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::Decoder;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::probe::ProbeResult;
+use symphonia::core::units;
+
+use crate::audio_format;
+use crate::backend::loader_loop::for_each_metadata_revision;
+
+/// The decoded samples of a track, made from the probe that also gave its metadata,
+/// so the file is opened and probed once. The encoder delay and padding are left out.
+pub struct TrackSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    total_duration: Option<Duration>,
+    buffer: SampleBuffer<f32>,
+    spec: SignalSpec,
+    offset: usize,      // in the buffer
+    end: usize,         // in the buffer, the samples after it are padding
+    trim: Option<Trim>, // when the demuxer doesn't trim the track itself
+    position: u64,      // frames decoded since the start of the stream, delay included
+}
+
+// encoder delay and length of a track, in frames
+#[derive(Clone, Copy)]
+struct Trim {
+    delay: u64,
+    frames: u64, // after the delay, the padding follows
+}
+
+impl TrackSource {
+    /// Decodes the first packet right away, so the channels and the sample rate are known.
+    pub fn new(mut probed: ProbeResult) -> Option<Self> {
+        let mut total_duration = audio_format::get_duration(&probed);
+        let (track_id, decoder) = audio_format::make_decoder(&probed)?;
+        let params = decoder.codec_params();
+        // symphonia reads the LAME header, not the iTunSMPB tag of AAC files
+        let trim = match params.delay {
+            Some(_) => None,
+            None => read_itunsmpb(&mut probed),
+        };
+        if let (Some(trim), Some(rate)) = (trim, params.sample_rate) {
+            total_duration = Some(Duration::from_secs_f64(
+                trim.frames as f64 / f64::from(rate),
+            ));
+        }
+        // replaced by the first packet
+        let spec = SignalSpec::new(0, Channels::FRONT_LEFT);
+        let mut source = Self {
+            format: probed.format,
+            decoder,
+            track_id,
+            total_duration,
+            buffer: SampleBuffer::new(0, spec),
+            spec,
+            offset: 0,
+            end: 0,
+            trim,
+            position: 0,
+        };
+        source.decode_next()?;
+        Some(source)
+    }
+
+    pub fn open(path: &Path) -> Option<Self> {
+        Self::new(audio_format::probe(path)?)
+    }
+
+    /// Fills the buffer with the next packet holding audio. Undecodable packets are skipped.
+    fn decode_next(&mut self) -> Option<()> {
+        loop {
+            let packet = self.format.next_packet().ok()?;
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(_)) => continue,
+                Err(_) => return None,
+            };
+            // some packets only hold metadata
+            if decoded.frames() == 0 {
+                continue;
+            }
+            let frames = decoded.frames() as u64;
+            let first_frame = self.position;
+            self.position += frames;
+            // the frames of the packet that are neither delay nor padding
+            let (start, end) = match self.trim {
+                None => (0, frames),
+                Some(trim) => {
+                    let audio_end = trim.delay + trim.frames;
+                    if first_frame >= audio_end {
+                        return None;
+                    }
+                    (
+                        trim.delay.saturating_sub(first_frame).min(frames),
+                        (audio_end - first_frame).min(frames),
+                    )
+                }
+            };
+            if start >= end {
+                continue;
+            }
+            self.spec = *decoded.spec();
+            let capacity = units::Duration::from(decoded.capacity() as u64);
+            self.buffer = SampleBuffer::new(capacity, self.spec);
+            self.buffer.copy_interleaved_ref(decoded);
+            let channels = self.spec.channels.count();
+            self.offset = start as usize * channels;
+            self.end = end as usize * channels;
+            return Some(());
+        }
+    }
+}
+
+impl Iterator for TrackSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.offset >= self.end {
+            self.decode_next()?;
+        }
+        let sample = *self.buffer.samples().get(self.offset)?;
+        self.offset += 1;
+        // the next packet is decoded right away, so that its length and format are known
+        // as soon as the current one is read
+        if self.offset >= self.end {
+            self.decode_next();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for TrackSource {
+    // what is left of the packet, the rest of it being padding. 0 only once the track ended.
+    fn current_span_len(&self) -> Option<usize> {
+        Some(self.end - self.offset)
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let target = match self.total_duration {
+            Some(total) => pos.min(total),
+            None => pos,
+        };
+        // the stream starts with the delay
+        let delay = self
+            .trim
+            .map_or(Duration::ZERO, |t| self.to_duration(t.delay));
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: (target + delay).into(),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|e| SeekError::Other(Box::new(TrackSeekError(e))))?;
+        self.decoder.reset();
+        self.offset = self.end; // decode from the new position
+
+        // the demuxer stops at the packet holding the target, skip to the target itself
+        if let Some(time_base) = self.decoder.codec_params().time_base {
+            let actual = self.to_frames(time_base.calc_time(seeked.actual_ts).into());
+            let required = self.to_frames(time_base.calc_time(seeked.required_ts).into());
+            self.position = actual;
+            // the delay is left out anyway
+            let first_kept = actual.max(self.trim.map_or(0, |t| t.delay));
+            let channels = usize::from(self.channels().max(1));
+            for _ in 0..required.saturating_sub(first_kept) as usize * channels {
+                self.next();
+            }
+        }
+        if self.offset >= self.end {
+            self.decode_next();
+        }
+        Ok(())
+    }
+}
+
+impl TrackSource {
+    fn to_frames(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * f64::from(self.sample_rate())).round() as u64
+    }
+
+    fn to_duration(&self, frames: u64) -> Duration {
+        Duration::from_secs_f64(frames as f64 / f64::from(self.sample_rate().max(1)))
+    }
+}
+
+/// Delay and length from an iTunSMPB tag, e.g. " 00000000 00000840 000001CA 00000000003F31F6 ...":
+/// the delay, the padding and the number of frames, in hexadecimal.
+fn read_itunsmpb(probed: &mut ProbeResult) -> Option<Trim> {
+    let mut value = None;
+    for_each_metadata_revision(probed, |revision| {
+        if let Some(tag) = revision.tags().iter().find(|t| t.key.ends_with("iTunSMPB")) {
+            value = Some(tag.value.to_string());
+        }
+    });
+    let fields = value?
+        .split_whitespace()
+        .map(|f| u64::from_str_radix(f, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let (&delay, &frames) = (fields.get(1)?, fields.get(3)?);
+    (frames > 0).then_some(Trim { delay, frames })
+}
+
+#[derive(Debug)]
+struct TrackSeekError(Error);
+
+impl fmt::Display for TrackSeekError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seek failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for TrackSeekError {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Writes a 16-bit PCM WAV file in the temporary folder and returns its path.
+    pub(crate) fn write_wav(
+        name: &str,
+        channels: u16,
+        sample_rate: u32,
+        samples: &[f32],
+    ) -> PathBuf {
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16).to_le_bytes())
+            .collect();
+        let block_align = channels * 2;
+        let mut wav = vec![];
+        wav.extend(b"RIFF");
+        wav.extend((36 + data.len() as u32).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // PCM
+        wav.extend(channels.to_le_bytes());
+        wav.extend(sample_rate.to_le_bytes());
+        wav.extend((sample_rate * u32::from(block_align)).to_le_bytes());
+        wav.extend(block_align.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend((data.len() as u32).to_le_bytes());
+        wav.extend(data);
+
+        let dir = std::env::temp_dir().join("rustify-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, wav).unwrap();
+        path
+    }
+
+    // reads the source span by span, checking that every span holds the samples it announced
+    fn read_spans(source: &mut TrackSource) -> Vec<usize> {
+        let mut spans = vec![];
+        loop {
+            let len = source.current_span_len().unwrap();
+            if len == 0 {
+                assert_eq!(source.next(), None);
+                return spans;
+            }
+            for _ in 0..len {
+                assert!(source.next().is_some());
+            }
+            spans.push(len);
+        }
+    }
+
+    #[test]
+    fn spans_cover_the_track() {
+        let samples = vec![0.25; 2 * 44_100];
+        let path = write_wav("spans.wav", 2, 44_100, &samples);
+        let mut source = TrackSource::open(&path).unwrap();
+        assert_eq!(source.channels(), 2);
+        assert_eq!(source.total_duration(), Some(Duration::from_secs(1)));
+        let spans = read_spans(&mut source);
+        assert!(spans.len() > 1);
+        assert_eq!(spans.iter().sum::<usize>(), samples.len());
+    }
+
+    #[test]
+    fn spans_after_a_seek_are_what_is_left() {
+        let samples: Vec<f32> = (0..44_100).map(|i| i as f32 / 44_100.0).collect();
+        let path = write_wav("seek.wav", 1, 44_100, &samples);
+        let mut source = TrackSource::open(&path).unwrap();
+        let first = source.current_span_len().unwrap();
+        source.next();
+        assert_eq!(source.current_span_len(), Some(first - 1));
+
+        source.try_seek(Duration::from_millis(250)).unwrap();
+        assert!(source.current_span_len().unwrap() > 0);
+        let left: usize = read_spans(&mut source).iter().sum();
+        assert_eq!(left, samples.len() - 11_025);
+
+        // partway through a packet
+        source.try_seek(Duration::from_millis(500)).unwrap();
+        let sample = source.next().unwrap();
+        assert!((sample - 0.5).abs() < 0.001, "{sample}");
+    }
+}
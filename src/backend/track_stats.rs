@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::WeightCurve;
+use crate::TRACK_STATS_RELATIVE_PATH;
+
+// no track is ruled out entirely
+const MIN_FACTOR: f64 = 0.01;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What the user did with a track.
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct TrackStat {
+    pub rating: Option<u8>, // 1 to 5 stars
+    pub plays: u32,
+    pub skips: u32,
+    pub last_played: Option<SystemTime>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct TrackStats {
+    tracks: HashMap<PathBuf, TrackStat>,
+}
+
+impl TrackStats {
+    pub fn get(&self, path: &Path) -> Option<&TrackStat> {
+        self.tracks.get(path)
+    }
+
+    pub fn record_play(&mut self, path: &Path, now: SystemTime) {
+        let stat = self.tracks.entry(path.to_path_buf()).or_default();
+        stat.plays += 1;
+        stat.last_played = Some(now);
+    }
+
+    pub fn record_skip(&mut self, path: &Path) {
+        self.tracks.entry(path.to_path_buf()).or_default().skips += 1;
+    }
+
+    pub fn set_rating(&mut self, path: &Path, rating: Option<u8>) {
+        self.tracks.entry(path.to_path_buf()).or_default().rating = rating.map(|r| r.clamp(1, 5));
+    }
+
+    /// Chance of the track to be picked by the weighted shuffle, relative to the others.
+    pub fn get_weight(&self, path: &Path, curve: &WeightCurve, now: SystemTime) -> f64 {
+        let Some(stat) = self.tracks.get(path) else {
+            return 1.0;
+        };
+        // unrated tracks count as 3 stars
+        let rating = f64::from(stat.rating.unwrap_or(3)) / 3.0;
+        let rating_factor = rating.powf(f64::from(curve.rating_exponent));
+
+        // back to full weight over a few times recency_days
+        // a clock set back since the last play can't tell how long ago it was
+        let elapsed = stat
+            .last_played
+            .and_then(|last| now.duration_since(last).ok());
+        let recency_factor = match elapsed {
+            Some(elapsed) if curve.recency_days > 0.0 => {
+                let days = elapsed.as_secs_f64() / DAY.as_secs_f64();
+                1.0 - (-days / f64::from(curve.recency_days)).exp()
+            }
+            _ => 1.0,
+        };
+
+        let skip_rate = f64::from(stat.skips) / f64::from(stat.plays.max(stat.skips).max(1));
+        let skip_factor = (1.0 - skip_rate).powf(f64::from(curve.skip_exponent));
+
+        rating_factor * recency_factor.max(MIN_FACTOR) * skip_factor.max(MIN_FACTOR)
+    }
+}
+
+pub fn read(dir: &Path) -> TrackStats {
+    let path = dir.join(TRACK_STATS_RELATIVE_PATH);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error in reading {}: {e}", path.display());
+            return TrackStats::default();
+        }
+    };
+    serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+        eprintln!("Error in parsing {}: {e}", path.display());
+        TrackStats::default()
+    })
+}
+
+pub fn write(dir: &Path, stats: &TrackStats) {
+    let path = dir.join(TRACK_STATS_RELATIVE_PATH);
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create file '{}': {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = serde_json::to_writer(BufWriter::new(file), stats) {
+        eprintln!("Failed to write to file '{}': {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVE: WeightCurve = WeightCurve {
+        rating_exponent: 1.0,
+        recency_days: 7.0,
+        skip_exponent: 1.0,
+    };
+
+    #[test]
+    fn played_tracks_get_their_weight_back_over_time() {
+        let path = Path::new("/music/a.mp3");
+        let played = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut stats = TrackStats::default();
+        stats.record_play(path, played);
+
+        let weight = |elapsed| stats.get_weight(path, &CURVE, played + elapsed);
+        assert_eq!(weight(Duration::ZERO), MIN_FACTOR);
+        let a_week = weight(7 * DAY);
+        assert!((a_week - (1.0 - (-1.0f64).exp())).abs() < 1e-9, "{a_week}");
+        assert!(weight(DAY) < a_week);
+        assert!(weight(100 * DAY) > 0.999);
+    }
+
+    #[test]
+    fn a_clock_set_back_does_not_count_as_just_played() {
+        let path = Path::new("/music/a.mp3");
+        let played = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut stats = TrackStats::default();
+        stats.record_play(path, played);
+        assert_eq!(stats.get_weight(path, &CURVE, played - DAY), 1.0);
+    }
+
+    #[test]
+    fn ratings_and_skips_scale_the_weight() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut stats = TrackStats::default();
+        let unknown = Path::new("/music/unknown.mp3");
+        let loved = Path::new("/music/loved.mp3");
+        let skipped = Path::new("/music/skipped.mp3");
+        stats.set_rating(loved, Some(6));
+        stats.record_skip(skipped);
+
+        assert_eq!(stats.get_weight(unknown, &CURVE, now), 1.0);
+        // clamped to 5 stars, against 3 for the unrated tracks
+        assert!((stats.get_weight(loved, &CURVE, now) - 5.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.get_weight(skipped, &CURVE, now), MIN_FACTOR);
+    }
+}
@@ -0,0 +1,340 @@
+use std::process::exit;
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::backend::watcher_messages::{Event, Request};
+
+#[cfg(target_os = "linux")]
+pub fn run(request_receiver: Receiver<Request>, event_sender: Sender<Event>) {
+    inotify_watcher::run(request_receiver, event_sender)
+}
+
+// no watcher on this platform: changes are picked up when the folder is reloaded
+#[cfg(not(target_os = "linux"))]
+pub fn run(request_receiver: Receiver<Request>, _event_sender: Sender<Event>) {
+    idle(request_receiver)
+}
+
+// keeps the thread (and the event sender) alive without watching anything
+fn idle(request_receiver: Receiver<Request>) {
+    loop {
+        if let Err(e) = request_receiver.recv() {
+            println!("Error in watcher thread: {e:?}");
+            exit(1);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify_watcher {
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::ffi::OsString;
+    use std::fs::read_dir;
+    use std::io::ErrorKind;
+    use std::path::{Path, PathBuf};
+    use std::process::exit;
+    use std::time::Duration;
+
+    use crossbeam_channel::{select, Receiver, Sender};
+    use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+    use crate::backend::scan_rules::{IgnoreFiles, ScanRules, IGNORE_FILE_NAME};
+    use crate::backend::watcher_messages::{Event, Request};
+
+    // how often the inotify queue is read, changes are sent once a whole tick passes without new ones
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    struct Watcher {
+        inotify: Inotify,
+        roots: Vec<PathBuf>,
+        rules: Option<ScanRules>,
+        watched_dirs: HashMap<WatchDescriptor, PathBuf>,
+        changed: BTreeSet<PathBuf>,
+    }
+
+    pub fn run(request_receiver: Receiver<Request>, event_sender: Sender<Event>) {
+        let inotify = match Inotify::init() {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                eprintln!("Failed to initialize inotify, the music folder won't be watched: {e}");
+                super::idle(request_receiver);
+                return;
+            }
+        };
+        let mut watcher = Watcher {
+            inotify,
+            roots: vec![],
+            rules: None,
+            watched_dirs: HashMap::new(),
+            changed: BTreeSet::new(),
+        };
+        let mut buffer = [0; 4096];
+
+        loop {
+            select! {
+                recv(request_receiver) -> res => match res {
+                    Ok(Request::Watch(roots, rules)) => watcher.watch(roots, rules),
+                    Err(e) => {
+                        println!("Error in watcher thread: {e:?}");
+                        exit(1);
+                    }
+                },
+                default(POLL_INTERVAL) => {},
+            }
+
+            let new_changes = watcher.read_events(&mut buffer);
+            if !new_changes && !watcher.changed.is_empty() {
+                let changed = std::mem::take(&mut watcher.changed);
+                println!("[WATCHER] {} paths changed", changed.len());
+                event_sender
+                    .send(Event::Changed(changed.into_iter().collect()))
+                    .unwrap();
+            }
+        }
+    }
+
+    impl Watcher {
+        fn watch(&mut self, roots: Vec<PathBuf>, rules: ScanRules) {
+            for wd in self.watched_dirs.drain().map(|(wd, _)| wd) {
+                let _ = self.inotify.watches().remove(wd);
+            }
+            self.changed.clear();
+            self.roots = roots;
+            self.rules = Some(rules);
+            for root in self.roots.clone() {
+                self.add_watches_from(&root, &root);
+            }
+            println!(
+                "[WATCHER] watching {} folders under {} roots",
+                self.watched_dirs.len(),
+                self.roots.len()
+            );
+        }
+
+        /// Watches `dir` and the folders below it that a scan from `root` would enter.
+        fn add_watches_from(&mut self, root: &Path, dir: &Path) {
+            let Some(rules) = self.rules.clone() else {
+                return;
+            };
+            if dir != root {
+                if dir.is_symlink() && !rules.follows_symlinks() {
+                    return;
+                }
+                if rules.check_from_root(root, dir, true).is_some() {
+                    return;
+                }
+            }
+            let depth = dir.strip_prefix(root).map_or(0, |p| p.components().count());
+            if depth > rules.max_depth() {
+                return;
+            }
+            let ignore_files = IgnoreFiles::from_root(root, dir);
+            self.add_watches(dir, &rules, &ignore_files, depth, &mut HashSet::new());
+        }
+
+        /// Same walk as a scan: `visited` keeps symlinks from looping.
+        fn add_watches(
+            &mut self,
+            dir: &Path,
+            rules: &ScanRules,
+            ignore_files: &IgnoreFiles,
+            depth: usize,
+            visited: &mut HashSet<WatchDescriptor>,
+        ) {
+            let mask = WatchMask::CREATE
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO
+                | WatchMask::DELETE_SELF
+                | WatchMask::ONLYDIR;
+            // a folder reached again gets the same watch descriptor
+            match self.inotify.watches().add(dir, mask) {
+                Ok(wd) if !visited.insert(wd.clone()) => return,
+                Ok(wd) => {
+                    self.watched_dirs.insert(wd, dir.to_path_buf());
+                }
+                Err(e) => {
+                    eprintln!("Failed to watch {}: {e}", dir.display());
+                    return;
+                }
+            }
+            if depth >= rules.max_depth() {
+                return;
+            }
+            let Ok(dir_iter) = read_dir(dir) else {
+                return;
+            };
+            for entry in dir_iter.flatten() {
+                let path = entry.path();
+                let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+                if (is_symlink && !rules.follows_symlinks()) || !path.is_dir() {
+                    continue;
+                }
+                if rules
+                    .check(&path)
+                    .or_else(|| ignore_files.check(&path, true))
+                    .is_some()
+                {
+                    continue;
+                }
+                let ignore_files = ignore_files.enter(&path);
+                self.add_watches(&path, rules, &ignore_files, depth + 1, visited);
+            }
+        }
+
+        /// Reads all the pending events. Returns whether there were any.
+        fn read_events(&mut self, buffer: &mut [u8]) -> bool {
+            let mut events: Vec<(WatchDescriptor, EventMask, Option<OsString>)> = vec![];
+            loop {
+                match self.inotify.read_events(buffer) {
+                    Ok(read) => events
+                        .extend(read.map(|e| (e.wd, e.mask, e.name.map(|n| n.to_os_string())))),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("Error in reading inotify events: {e}");
+                        break;
+                    }
+                }
+            }
+
+            for (wd, mask, name) in &events {
+                if mask.contains(EventMask::Q_OVERFLOW) {
+                    // events were lost, the whole folders must be checked
+                    self.changed.extend(self.roots.iter().cloned());
+                    continue;
+                }
+                if mask.contains(EventMask::IGNORED) {
+                    self.watched_dirs.remove(wd);
+                    continue;
+                }
+                let Some(dir) = self.watched_dirs.get(wd) else {
+                    continue;
+                };
+                let path = match name {
+                    Some(name) => dir.join(name),
+                    None => dir.clone(),
+                };
+                if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO | EventMask::CLOSE_WRITE)
+                {
+                    if let Some(root) = self.roots.iter().find(|r| path.starts_with(r)).cloned() {
+                        if path.ends_with(IGNORE_FILE_NAME) {
+                            // folders may no longer be ignored
+                            self.add_watches_from(&root, path.parent().unwrap_or(&root));
+                        } else if path.is_dir() {
+                            self.add_watches_from(&root, &path);
+                        }
+                    }
+                }
+                self.changed.insert(path);
+            }
+
+            !events.is_empty()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::os::unix::fs::symlink;
+        use std::{env, fs, process};
+
+        use crate::settings::{Settings, SymlinkPolicy};
+
+        fn make_dir(name: &str) -> PathBuf {
+            let dir =
+                env::temp_dir().join(format!("rustify-watcher-tests-{}/{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        // root/a/b/c/d, a hidden, an excluded and an ignored folder, a link back to the
+        // root and a link out of it
+        fn make_tree(name: &str) -> (PathBuf, PathBuf) {
+            let dir = make_dir(name);
+            let root = dir.join("root");
+            let outside = dir.join("outside");
+            for folder in ["a/b/c/d", ".hidden", "excluded", "ignored"] {
+                fs::create_dir_all(root.join(folder)).unwrap();
+            }
+            fs::create_dir_all(&outside).unwrap();
+            fs::write(root.join(IGNORE_FILE_NAME), "ignored/\n").unwrap();
+            symlink(&root, root.join("a/loop")).unwrap();
+            symlink(&outside, root.join("out")).unwrap();
+            (root, outside)
+        }
+
+        fn make_watcher(roots: Vec<PathBuf>, symlinks: SymlinkPolicy) -> Watcher {
+            let mut settings = Settings::default();
+            settings.exclude_globs = vec!["excluded".to_string()];
+            settings.max_scan_depth = 2;
+            settings.symlinks = symlinks;
+            let mut watcher = Watcher {
+                inotify: Inotify::init().unwrap(),
+                roots: vec![],
+                rules: None,
+                watched_dirs: HashMap::new(),
+                changed: BTreeSet::new(),
+            };
+            watcher.watch(roots, ScanRules::new(&settings));
+            watcher
+        }
+
+        fn get_watched(watcher: &Watcher, root: &Path) -> BTreeSet<String> {
+            let relative = |p: &PathBuf| match p.strip_prefix(root) {
+                Ok(p) => p.display().to_string(),
+                Err(_) => p.file_name().unwrap().to_string_lossy().into_owned(),
+            };
+            watcher.watched_dirs.values().map(relative).collect()
+        }
+
+        #[test]
+        fn only_the_scanned_folders_are_watched() {
+            let (root, _) = make_tree("scanned");
+            let watcher = make_watcher(vec![root.clone()], SymlinkPolicy::Follow);
+            // the loop back to the root is watched once, the link out of it like a folder
+            assert_eq!(
+                get_watched(&watcher, &root),
+                BTreeSet::from(["".into(), "a".into(), "a/b".into(), "out".into()])
+            );
+        }
+
+        #[test]
+        fn links_are_not_watched_when_skipped() {
+            let (root, _) = make_tree("skipped-links");
+            let watcher = make_watcher(vec![root.clone()], SymlinkPolicy::Skip);
+            assert_eq!(
+                get_watched(&watcher, &root),
+                BTreeSet::from(["".into(), "a".into(), "a/b".into()])
+            );
+        }
+
+        #[test]
+        fn new_folders_are_watched_unless_excluded() {
+            let (root, _) = make_tree("new-folders");
+            let mut watcher = make_watcher(vec![root.clone()], SymlinkPolicy::Skip);
+            for folder in ["new/inner/too-deep", "excluded-too/excluded", ".new"] {
+                fs::create_dir_all(root.join(folder)).unwrap();
+            }
+            fs::create_dir(root.join("a/b/too-deep")).unwrap();
+            watcher.read_events(&mut [0; 4096]);
+            assert_eq!(
+                get_watched(&watcher, &root),
+                BTreeSet::from([
+                    "".into(),
+                    "a".into(),
+                    "a/b".into(),
+                    "new".into(),
+                    "new/inner".into(),
+                    "excluded-too".into(),
+                ])
+            );
+
+            // no longer ignored
+            fs::write(root.join(IGNORE_FILE_NAME), "").unwrap();
+            watcher.read_events(&mut [0; 4096]);
+            assert!(get_watched(&watcher, &root).contains("ignored"));
+        }
+    }
+}
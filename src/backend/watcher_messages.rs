@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+use crate::backend::scan_rules::ScanRules;
+
+#[derive(Clone)]
+pub(crate) enum Request {
+    Watch(Vec<PathBuf>, ScanRules), // replaces the watched folders, skipping those not scanned
+}
+
+pub(crate) enum Event {
+    Changed(Vec<PathBuf>), // files or folders created, modified, moved or deleted
+}
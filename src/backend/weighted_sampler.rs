@@ -0,0 +1,141 @@
+use rand::Rng;
+
+/// Picks indices with a chance proportional to their weight. Weights sit in a Fenwick tree,
+/// so both a pick and a weight change take O(log n), whatever the size of the library.
+pub struct WeightedSampler {
+    weights: Vec<f64>,
+    tree: Vec<f64>, // 1-based, tree[i] sums the weights of (i - lowbit(i), i]
+}
+
+impl WeightedSampler {
+    pub fn new(weights: Vec<f64>) -> Self {
+        // linear construction: every node adds itself to its parent
+        let mut tree = vec![0.0; weights.len() + 1];
+        for (i, w) in weights.iter().enumerate() {
+            tree[i + 1] += w;
+            let parent = (i + 1) + lowbit(i + 1);
+            if parent < tree.len() {
+                tree[parent] += tree[i + 1];
+            }
+        }
+        Self { weights, tree }
+    }
+
+    pub fn set(&mut self, i: usize, weight: f64) {
+        let delta = weight - self.weights[i];
+        self.weights[i] = weight;
+        let mut node = i + 1;
+        while node < self.tree.len() {
+            self.tree[node] += delta;
+            node += lowbit(node);
+        }
+    }
+
+    pub fn get_total(&self) -> f64 {
+        let mut total = 0.0;
+        let mut node = self.weights.len();
+        while node > 0 {
+            total += self.tree[node];
+            node -= lowbit(node);
+        }
+        total
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<usize> {
+        let total = self.get_total();
+        if total <= 0.0 {
+            return None;
+        }
+        // walk down the tree to the first index whose prefix sum exceeds the target
+        let mut target = rng.gen_range(0.0..total);
+        let mut node = 0;
+        let mut step = self.weights.len().next_power_of_two();
+        while step > 0 {
+            let next = node + step;
+            if next < self.tree.len() && self.tree[next] <= target {
+                target -= self.tree[next];
+                node = next;
+            }
+            step /= 2;
+        }
+        // rounding may land on the end, or on a track weighted 0
+        self.get_nearest_weighted(node.min(self.weights.len() - 1))
+    }
+
+    // the closest index to `i` with a positive weight, none if rounding left a total
+    // above 0 after every weight was set to 0
+    fn get_nearest_weighted(&self, i: usize) -> Option<usize> {
+        let before = self.weights[..=i].iter().rposition(|&w| w > 0.0);
+        let after = self.weights[i..]
+            .iter()
+            .position(|&w| w > 0.0)
+            .map(|j| i + j);
+        match (before, after) {
+            (Some(b), Some(a)) if i - b <= a - i => Some(b),
+            (_, Some(a)) => Some(a),
+            (b, None) => b,
+        }
+    }
+}
+
+fn lowbit(i: usize) -> usize {
+    i & i.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn picks_follow_the_weights() {
+        let mut sampler = WeightedSampler::new(vec![1.0, 2.0, 0.0, 1.0]);
+        sampler.set(3, 5.0);
+        assert_eq!(sampler.get_total(), 8.0);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = [0usize; 4];
+        for _ in 0..80_000 {
+            counts[sampler.sample(&mut rng).unwrap()] += 1;
+        }
+        assert_eq!(counts[2], 0);
+        for (count, expected) in counts.iter().zip([10_000, 20_000, 0, 50_000]) {
+            assert!(count.abs_diff(expected) < 1_000, "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn never_picks_a_weight_of_zero() {
+        // the weights set to 0 leave rounding errors in the tree
+        let mut sampler = WeightedSampler::new((1..=100).map(|i| f64::from(i) / 7.0).collect());
+        for i in 0..99 {
+            sampler.set(i, 0.0);
+        }
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..10_000 {
+            assert_eq!(sampler.sample(&mut rng), Some(99));
+        }
+        for i in 0..99 {
+            assert_eq!(sampler.get_nearest_weighted(i), Some(99));
+        }
+    }
+
+    #[test]
+    fn nearest_weighted_index_goes_either_way() {
+        let sampler = WeightedSampler::new(vec![1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(sampler.get_nearest_weighted(1), Some(0));
+        assert_eq!(sampler.get_nearest_weighted(4), Some(5));
+        assert_eq!(sampler.get_nearest_weighted(5), Some(5));
+    }
+
+    #[test]
+    fn nothing_to_pick_once_every_weight_is_zero() {
+        let mut sampler = WeightedSampler::new(vec![0.1, 0.2, 0.3]);
+        for i in 0..3 {
+            sampler.set(i, 0.0);
+        }
+        let mut rng = StdRng::seed_from_u64(3);
+        assert_eq!(sampler.sample(&mut rng), None);
+        assert_eq!(WeightedSampler::new(vec![]).sample(&mut rng), None);
+    }
+}
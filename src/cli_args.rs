@@ -0,0 +1,24 @@
+use std::env;
+
+/// Options given on the command line. They take precedence over settings.json.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CliArgs {
+    pub seed: Option<u64>, // --seed <n>: seed of the random picks
+    pub replay: bool,      // --replay: the picks, seed and starting state of the last session
+}
+
+pub fn parse() -> CliArgs {
+    let mut args = CliArgs::default();
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--seed" => match iter.next().map(|s| s.parse()) {
+                Some(Ok(seed)) => args.seed = Some(seed),
+                _ => eprintln!("--seed needs a number, ignored"),
+            },
+            "--replay" => args.replay = true,
+            _ => eprintln!("Unknown argument '{arg}', ignored"),
+        }
+    }
+    args
+}
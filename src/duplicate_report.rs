@@ -0,0 +1,34 @@
+use std::path::{Path, PathBuf};
+
+/// Copies of the same track found in the library.
+#[derive(Debug, Clone, Default)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub paths: Vec<PathBuf>, // the preferred copy first
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DuplicateKind {
+    Exact,     // same file content
+    Recording, // same audio, e.g. re-encoded in another format
+}
+
+impl DuplicateReport {
+    /// All the copies but the preferred one of each group.
+    pub fn get_non_preferred(&self) -> impl Iterator<Item = &PathBuf> {
+        self.groups.iter().flat_map(|g| g.paths.iter().skip(1))
+    }
+
+    /// The copy played instead of `path`, if `path` is not the preferred one of its group.
+    pub fn get_preferred_copy(&self, path: &Path) -> Option<&PathBuf> {
+        self.groups
+            .iter()
+            .find(|g| g.paths.iter().skip(1).any(|p| p == path))
+            .map(|g| &g.paths[0])
+    }
+}
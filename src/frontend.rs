@@ -1,6 +1,9 @@
 mod central_panel;
+mod duplicates_window;
 mod eframe_app;
+mod files_side_panel;
 mod path_top_panel;
+mod queue_side_panel;
 mod track_bottom_panel;
 
 pub use eframe_app::App;
@@ -1,7 +1,7 @@
 use crate::frontend::eframe_app::EmptyDisplayMessage;
 use crate::frontend::App;
 use crate::music_dir_creation_error::MusicDirCreationError;
-use eframe::egui::{CentralPanel, Context, Image};
+use eframe::egui::{CentralPanel, Color32, Context, Image, RichText, ScrollArea, Ui};
 
 impl App {
     pub(crate) fn spawn_image_central_panel(&mut self, ctx: &Context) {
@@ -22,27 +22,58 @@ impl App {
         ctx: &Context,
         message: EmptyDisplayMessage,
     ) {
-        let text: &str = match message {
-            EmptyDisplayMessage::SelectFolder => "Select a folder",
-            EmptyDisplayMessage::Error(e) => match e {
-                MusicDirCreationError::NotFound => "Error: path not found",
-                MusicDirCreationError::NotDir => "Error: selected path is not a folder",
-                MusicDirCreationError::Empty => "Error: no .mp3 files found inside the selected folder and its relative sub-folders",
-                MusicDirCreationError::Unknown => "An unknown error occurred",
-            }
+        let text = match &message {
+            EmptyDisplayMessage::SelectFolder => "Select a folder".to_string(),
+            EmptyDisplayMessage::Error(e) => get_error_text(e),
         };
+        let has_details = !self.root_errors.is_empty() || !self.scan_report.problems.is_empty();
         CentralPanel::default().show(ctx, |ui| {
-            ui.centered_and_justified(|ui| {
-                ui.label(text);
-            });
+            if matches!(message, EmptyDisplayMessage::Error(_)) && has_details {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(ui.available_height() / 3.0);
+                    ui.label(text);
+                    ui.collapsing("Details", |ui| self.show_scan_details(ui));
+                });
+            } else {
+                ui.centered_and_justified(|ui| {
+                    ui.label(text);
+                });
+            }
+        });
+    }
+
+    /// Lists the folders that failed and the entries the last scan skipped.
+    pub(crate) fn show_scan_details(&self, ui: &mut Ui) {
+        ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+            for e in self.root_errors.values() {
+                ui.colored_label(Color32::LIGHT_RED, RichText::new(e.to_string()).monospace());
+            }
+            for problem in &self.scan_report.problems {
+                ui.label(RichText::new(problem.to_string()).monospace());
+            }
         });
     }
 
     pub(crate) fn spawn_loading_central_panel(&mut self, ctx: &Context) {
         CentralPanel::default().show(ctx, |ui| {
-            ui.centered_and_justified(|ui| {
-                ui.label("Loading tracks...");
+            ui.centered_and_justified(|ui| match &self.scan_progress {
+                None => {
+                    ui.label("Loading tracks...");
+                }
+                Some((dirs, files, current_path)) => {
+                    ui.label(format!(
+                        "Scanning: {dirs} folders, {files} files\n{}",
+                        current_path.display()
+                    ));
+                }
             });
         });
     }
 }
+
+pub(crate) fn get_error_text(e: &MusicDirCreationError) -> String {
+    match e {
+        MusicDirCreationError::Cancelled => "Scan cancelled".to_string(),
+        e => format!("Error: {e}"),
+    }
+}
@@ -0,0 +1,73 @@
+use crate::duplicate_report::DuplicateKind;
+use crate::frontend::App;
+use crate::messages::Request;
+use eframe::egui::{Button, Context, RichText, ScrollArea, Window};
+
+impl App {
+    pub(crate) fn spawn_duplicates_window(&mut self, ctx: &Context) {
+        let mut open = self.show_duplicates;
+        Window::new("Duplicates")
+            .open(&mut open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let searching = self.duplicates_progress.is_some();
+                    if ui
+                        .add_enabled(!searching, Button::new("Find duplicates"))
+                        .clicked()
+                    {
+                        self.req_sender.send(Request::FindDuplicates).unwrap();
+                        self.duplicates_progress = Some((0, 0));
+                    }
+                    if let Some((done, total)) = self.duplicates_progress {
+                        ui.spinner();
+                        if total > 0 {
+                            ui.weak(format!("reading {done} / {total}"));
+                        }
+                    }
+                });
+                if ui
+                    .checkbox(
+                        &mut self.prefer_one_copy_input,
+                        "Play only the preferred copy of each group",
+                    )
+                    .changed()
+                {
+                    self.req_sender
+                        .send(Request::SetPreferOneCopy(self.prefer_one_copy_input))
+                        .unwrap();
+                }
+                ui.separator();
+
+                let Some(report) = &self.duplicates else {
+                    ui.weak("Not searched yet");
+                    return;
+                };
+                if report.groups.is_empty() {
+                    ui.weak("No duplicates found");
+                    return;
+                }
+                ui.label(format!("{} groups", report.groups.len()));
+                ScrollArea::vertical().show(ui, |ui| {
+                    for group in &report.groups {
+                        let kind = match group.kind {
+                            DuplicateKind::Exact => "Exact copies",
+                            DuplicateKind::Recording => "Same recording",
+                        };
+                        ui.strong(kind);
+                        // the preferred copy comes first
+                        for (i, path) in group.paths.iter().enumerate() {
+                            let text = RichText::new(path.display().to_string()).monospace();
+                            if i == 0 {
+                                ui.label(text);
+                            } else {
+                                ui.weak(text);
+                            }
+                        }
+                        ui.add_space(5.0);
+                    }
+                });
+            });
+        self.show_duplicates = open;
+    }
+}
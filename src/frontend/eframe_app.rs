@@ -1,12 +1,15 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::duplicate_report::DuplicateReport;
 use crate::frontend::eframe_app::EmptyDisplayMessage::Error;
 use crate::image_utils;
 use crate::messages::{Event, Request};
 use crate::music_dir_creation_error::MusicDirCreationError;
-use crate::settings::Settings;
+use crate::scan_report::ScanReport;
+use crate::settings::{MusicRoot, PlayOrder, Repeat, Settings};
 use crate::track_metadata::TrackMetaData;
 use crossbeam_channel::{Receiver, Sender};
 use eframe::egui::{CentralPanel, Context, TextureHandle, TextureOptions};
@@ -14,14 +17,14 @@ use eframe::{CreationContext, Frame};
 
 const DEFAULT_TEXTURE_PATH: &str = "assets/cover.png";
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub(crate) enum AppState {
     Empty(EmptyDisplayMessage),
     LoadingNewMusicDir,
     Playing(ProgressBarState, PauseButtonState, PauseButtonAction),
 }
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub(crate) enum EmptyDisplayMessage {
     SelectFolder,
     Error(MusicDirCreationError),
@@ -46,11 +49,32 @@ pub(crate) enum PauseButtonAction {
 }
 
 pub struct App {
-    pub(crate) root_music_path_input: String,
+    pub(crate) music_roots_input: Vec<MusicRoot>,
+    pub(crate) new_root_input: String,
+    pub(crate) root_errors: HashMap<PathBuf, MusicDirCreationError>,
     pub(crate) volume_input: f32,
+    pub(crate) library_change: Option<(usize, usize)>, // tracks added and removed on disk
+    pub(crate) loaded_tracks: Option<usize>,           // by the last scan
+    pub(crate) scan_report: ScanReport,
+    pub(crate) scan_progress: Option<(usize, usize, PathBuf)>, // folders, files, current folder
+    pub(crate) explain_input: String,
+    pub(crate) show_duplicates: bool,
+    pub(crate) show_queue: bool,
+    pub(crate) show_files: bool,
+    pub(crate) browse_dir: Option<PathBuf>, // none for the list of music folders
+    pub(crate) browse_entries: Vec<(PathBuf, bool)>, // of browse_dir, true for folders
+    pub(crate) queue: Vec<(PathBuf, Arc<TrackMetaData>)>, // upcoming tracks
+    pub(crate) queue_textures: HashMap<PathBuf, TextureHandle>, // covers of the upcoming tracks
+    pub(crate) prefer_one_copy_input: bool,
+    pub(crate) play_order_input: PlayOrder,
+    pub(crate) repeat_input: Repeat,
+    pub(crate) duplicates: Option<DuplicateReport>,
+    pub(crate) duplicates_progress: Option<(usize, usize)>, // while searching
+    pub(crate) explanation: Option<(PathBuf, String)>,
     pub(crate) progress: Duration,
     pub(crate) state: AppState,
     pub(crate) current_track_metadata: Option<Arc<TrackMetaData>>,
+    pub(crate) current_rating: Option<u8>,
     pub(crate) current_texture: Option<TextureHandle>,
     pub(crate) default_texture: TextureHandle,
     pub(crate) req_sender: Sender<Request>,
@@ -71,11 +95,32 @@ impl App {
         let default_texture = load_default_texture(&cc.egui_ctx);
 
         Self {
-            root_music_path_input: initial_settings.root_music_path,
+            music_roots_input: initial_settings.music_roots,
+            new_root_input: String::new(),
+            root_errors: HashMap::new(),
             volume_input: initial_settings.volume,
+            library_change: None,
+            loaded_tracks: None,
+            scan_report: ScanReport::default(),
+            scan_progress: None,
+            explain_input: String::new(),
+            show_duplicates: false,
+            show_queue: false,
+            show_files: false,
+            browse_dir: None,
+            browse_entries: Vec::new(),
+            queue: Vec::new(),
+            queue_textures: HashMap::new(),
+            prefer_one_copy_input: initial_settings.prefer_one_copy,
+            play_order_input: initial_settings.play_order,
+            repeat_input: initial_settings.repeat,
+            duplicates: None,
+            duplicates_progress: None,
+            explanation: None,
             progress: Duration::from_secs(0),
             state: AppState::Empty(EmptyDisplayMessage::SelectFolder),
             current_track_metadata: None,
+            current_rating: None,
             current_texture: None,
             default_texture,
             req_sender,
@@ -110,9 +155,18 @@ impl App {
                         }
                     }
                 }
+                Event::RestoringSession => {
+                    self.state = AppState::LoadingNewMusicDir;
+                }
+                Event::CurrentRating(rating) => {
+                    self.current_rating = rating;
+                }
+                Event::QueueChanged(upcoming) => {
+                    self.update_queue(ctx, upcoming);
+                }
                 Event::ProgressUpdate(d) => match self.state {
-                    AppState::Empty(_) => unreachable!(),
-                    AppState::LoadingNewMusicDir => unreachable!(),
+                    AppState::Empty(_) => {}           // sent before a folder error
+                    AppState::LoadingNewMusicDir => {} // sent before the folders changed
                     AppState::Playing(progress_bar_state, _, _) => match progress_bar_state {
                         ProgressBarState::Active => {
                             self.set_progress_rounded(d);
@@ -121,8 +175,8 @@ impl App {
                     },
                 },
                 Event::JumpedTo(d) => match self.state {
-                    AppState::Empty(_) => unreachable!(),
-                    AppState::LoadingNewMusicDir => unreachable!(),
+                    AppState::Empty(_) => {}           // sent before a folder error
+                    AppState::LoadingNewMusicDir => {} // sent before the folders changed
                     AppState::Playing(progress_bar_state, x, y) => match progress_bar_state {
                         ProgressBarState::Active => {
                             self.set_progress_rounded(d);
@@ -134,7 +188,7 @@ impl App {
                     },
                 },
                 Event::NowPlaying => match self.state {
-                    AppState::Empty(_) => unreachable!(),
+                    AppState::Empty(_) => {} // sent before a folder error
                     AppState::LoadingNewMusicDir => {}
                     AppState::Playing(x, _, _) => {
                         self.state =
@@ -142,8 +196,8 @@ impl App {
                     }
                 },
                 Event::NowPaused => match self.state {
-                    AppState::Empty(_) => unreachable!(),
-                    AppState::LoadingNewMusicDir => unreachable!(),
+                    AppState::Empty(_) => {}           // sent before a folder error
+                    AppState::LoadingNewMusicDir => {} // a paused session being restored
                     AppState::Playing(x, _, _) => {
                         self.state =
                             AppState::Playing(x, PauseButtonState::Active, PauseButtonAction::Play)
@@ -151,10 +205,43 @@ impl App {
                 },
                 Event::NewSettings(s) => {
                     self.volume_input = s.volume;
-                    self.root_music_path_input = s.root_music_path;
+                    self.music_roots_input = s.music_roots;
+                    self.prefer_one_copy_input = s.prefer_one_copy;
+                    self.play_order_input = s.play_order;
+                    self.repeat_input = s.repeat;
                 }
                 Event::DirError(e) => {
                     self.state = AppState::Empty(Error(e));
+                    self.scan_progress = None;
+                }
+                Event::RootErrors(errors) => {
+                    self.root_errors = errors.into_iter().collect();
+                }
+                Event::LibraryChanged { added, removed } => {
+                    let (total_added, total_removed) = self.library_change.unwrap_or((0, 0));
+                    self.library_change = Some((total_added + added, total_removed + removed));
+                }
+                Event::ScanFinished { tracks, report } => {
+                    self.loaded_tracks = Some(tracks);
+                    self.scan_report = report;
+                    self.scan_progress = None;
+                }
+                Event::DuplicatesProgress { done, total } => {
+                    self.duplicates_progress = Some((done, total));
+                }
+                Event::DuplicatesFound(report) => {
+                    self.duplicates = Some(report);
+                    self.duplicates_progress = None;
+                }
+                Event::ScanProgress {
+                    dirs,
+                    files,
+                    current_path,
+                } => {
+                    self.scan_progress = Some((dirs, files, current_path));
+                }
+                Event::ExclusionExplained(path, explanation) => {
+                    self.explanation = Some((path, explanation));
                 }
             }
         }
@@ -197,9 +284,26 @@ impl App {
         }
     }
 
+    fn update_queue(&mut self, ctx: &Context, upcoming: Vec<(PathBuf, Arc<TrackMetaData>)>) {
+        // covers are loaded once per track, and dropped when it leaves the queue
+        self.queue_textures
+            .retain(|path, _| upcoming.iter().any(|(p, _)| p == path));
+        for (path, metadata) in &upcoming {
+            if self.queue_textures.contains_key(path) {
+                continue;
+            }
+            if let Some(image) = metadata.image.clone() {
+                let texture =
+                    ctx.load_texture(path.to_string_lossy(), image, TextureOptions::default());
+                self.queue_textures.insert(path.clone(), texture);
+            }
+        }
+        self.queue = upcoming;
+    }
+
     pub(crate) fn get_current_track_duration(&self) -> Option<Duration> {
         let metadata = self.current_track_metadata.as_ref()?;
-        metadata.duration.clone()
+        metadata.duration
     }
 }
 
@@ -207,15 +311,22 @@ impl eframe::App for App {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         self.read_events(ctx);
         self.spawn_path_top_panel(ctx);
-        match self.state {
+        self.spawn_duplicates_window(ctx);
+        match &self.state {
             AppState::Empty(message) => {
-                self.spawn_empty_central_panel(ctx, message);
+                self.spawn_empty_central_panel(ctx, message.clone());
             }
             AppState::LoadingNewMusicDir => {
                 self.spawn_loading_central_panel(ctx);
             }
             AppState::Playing(_, _, _) => {
                 self.spawn_track_bottom_panel(ctx);
+                if self.show_files {
+                    self.spawn_files_side_panel(ctx);
+                }
+                if self.show_queue {
+                    self.spawn_queue_side_panel(ctx);
+                }
                 if self.current_track_metadata.is_some() {
                     self.spawn_image_central_panel(ctx);
                 } else {
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audio_format;
+use crate::frontend::App;
+use crate::messages::Request;
+use eframe::egui::{Align, Button, Context, Layout, ScrollArea, SidePanel, Ui};
+
+impl App {
+    pub(crate) fn spawn_files_side_panel(&mut self, ctx: &Context) {
+        SidePanel::left("files")
+            .default_width(250.0)
+            .show(ctx, |ui| {
+                ui.add_space(5.0);
+                ui.heading("Files");
+                let mut opened = None;
+                match &self.browse_dir {
+                    None => {
+                        ui.weak("Music folders");
+                    }
+                    Some(dir) => {
+                        ui.horizontal(|ui| {
+                            if ui.small_button("⬆").on_hover_text("Up").clicked() {
+                                // above a music folder come the music folders themselves
+                                let is_root = self
+                                    .music_roots_input
+                                    .iter()
+                                    .any(|r| dir == Path::new(&r.path));
+                                opened =
+                                    Some(dir.parent().filter(|_| !is_root).map(Path::to_path_buf));
+                            }
+                            ui.weak(dir.display().to_string());
+                        });
+                    }
+                }
+                ui.separator();
+
+                let mut request = None;
+                ScrollArea::vertical().show(ui, |ui| {
+                    let entries: Vec<(PathBuf, bool)> = match &self.browse_dir {
+                        None => self
+                            .music_roots_input
+                            .iter()
+                            .filter(|r| r.enabled)
+                            .map(|r| (PathBuf::from(&r.path), true))
+                            .collect(),
+                        Some(_) => self.browse_entries.clone(),
+                    };
+                    for (path, is_dir) in entries {
+                        ui.horizontal(|ui| {
+                            let name = match (&self.browse_dir, path.file_name()) {
+                                (Some(_), Some(name)) => name.to_string_lossy().into_owned(),
+                                _ => path.display().to_string(),
+                            };
+                            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                                if let Some(r) = spawn_track_actions(ui, &path) {
+                                    request = Some(r);
+                                }
+                                ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
+                                    if is_dir {
+                                        let button = Button::new(format!("📁 {name}")).frame(false);
+                                        if ui.add(button).clicked() {
+                                            opened = Some(Some(path.clone()));
+                                        }
+                                    } else {
+                                        ui.label(format!("🎵 {name}"));
+                                    }
+                                });
+                            });
+                        });
+                    }
+                });
+                if let Some(request) = request {
+                    self.req_sender.send(request).unwrap();
+                }
+                if let Some(dir) = opened {
+                    self.open_browse_dir(dir);
+                }
+            });
+    }
+
+    fn open_browse_dir(&mut self, dir: Option<PathBuf>) {
+        self.browse_entries = match &dir {
+            None => vec![],
+            Some(dir) => read_browse_entries(dir),
+        };
+        self.browse_dir = dir;
+    }
+}
+
+fn spawn_track_actions(ui: &mut Ui, path: &Path) -> Option<Request> {
+    let mut request = None;
+    if ui
+        .small_button("➕")
+        .on_hover_text("Add to the queue")
+        .clicked()
+    {
+        request = Some(Request::Enqueue(vec![path.to_path_buf()]));
+    }
+    if ui.small_button("↪").on_hover_text("Play next").clicked() {
+        request = Some(Request::PlayNext(path.to_path_buf()));
+    }
+    if ui.small_button("▶").on_hover_text("Play now").clicked() {
+        request = Some(Request::PlayNow(path.to_path_buf()));
+    }
+    request
+}
+
+/// Folders first, then the files that may be tracks, in natural name order.
+fn read_browse_entries(dir: &Path) -> Vec<(PathBuf, bool)> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            eprintln!("UI: failed to read {}: {e}", dir.display());
+            return vec![];
+        }
+    };
+    let mut entries: Vec<(PathBuf, bool)> = read_dir
+        .flatten()
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| (e.path(), e.path().is_dir()))
+        .filter(|(path, is_dir)| *is_dir || !audio_format::is_skipped(path))
+        .collect();
+    entries.sort_by(|(a, a_dir), (b, b_dir)| {
+        b_dir
+            .cmp(a_dir)
+            .then_with(|| natord::compare(&a.to_string_lossy(), &b.to_string_lossy()))
+    });
+    entries
+}
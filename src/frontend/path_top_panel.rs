@@ -1,39 +1,131 @@
+use crate::frontend::central_panel::get_error_text;
 use crate::frontend::eframe_app::AppState;
 use crate::frontend::App;
 use crate::messages::Request;
-use eframe::egui::{Align, Button, Context, Layout, TextEdit, TextStyle, TopBottomPanel, Vec2};
-use std::path::PathBuf;
+use crate::settings::MusicRoot;
+use eframe::egui::{
+    Align, Button, Color32, Context, Layout, RichText, TextEdit, TextStyle, TopBottomPanel, Vec2,
+};
+use std::path::{Path, PathBuf};
 
 impl App {
     pub(crate) fn spawn_path_top_panel(&mut self, ctx: &Context) {
         TopBottomPanel::top("path").show(ctx, |ui| {
             ui.add_space(5.0);
-            ui.label("Music folder");
+            ui.horizontal(|ui| {
+                ui.label("Music folders");
+                if let Some((added, removed)) = self.library_change {
+                    ui.weak(format!(
+                        "library updated: {added} tracks added, {removed} removed"
+                    ));
+                }
+                if let (Some((_, files, _)), AppState::Playing(..)) =
+                    (&self.scan_progress, &self.state)
+                {
+                    ui.weak(format!("scanning: {files} files"));
+                }
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    ui.toggle_value(&mut self.show_duplicates, "Duplicates");
+                    ui.toggle_value(&mut self.show_queue, "Queue");
+                    ui.toggle_value(&mut self.show_files, "Files");
+                });
+            });
+
+            // partial success: what was loaded and what was skipped
+            if let Some(tracks) = self.loaded_tracks {
+                let skipped = self.scan_report.problems.len();
+                let failed = self.root_errors.len();
+                if skipped + failed > 0 {
+                    let mut summary = format!("Loaded {tracks} tracks");
+                    if skipped > 0 {
+                        summary += &format!(", {skipped} entries skipped");
+                    }
+                    if failed > 0 {
+                        summary += &format!(", {failed} folders failed");
+                    }
+                    ui.collapsing(RichText::new(summary).color(Color32::YELLOW), |ui| {
+                        self.show_scan_details(ui)
+                    });
+                }
+            }
+            ui.add_space(5.0);
+
+            // one row per folder: enable toggle, path, error and remove button
+            let mut removed_root = None;
+            for (i, root) in self.music_roots_input.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut root.enabled, RichText::new(&root.path).monospace());
+                    if let Some(e) = self.root_errors.get(Path::new(&root.path)) {
+                        ui.colored_label(Color32::LIGHT_RED, get_error_text(e));
+                    }
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        if ui.small_button("🗑").clicked() {
+                            removed_root = Some(i);
+                        }
+                    });
+                });
+            }
+            if let Some(i) = removed_root {
+                self.music_roots_input.remove(i);
+            }
+
             ui.add_space(5.0);
             ui.allocate_ui_with_layout(
                 Vec2::new(ui.available_width(), 50.0),
                 Layout::right_to_left(Align::TOP),
                 |ui| {
-                    let response = ui.add_enabled(
-                        self.state != AppState::LoadingNewMusicDir,
-                        Button::new("🔀"),
-                    );
-
-                    if response.clicked() {
+                    // a new scan cancels the running one
+                    if ui.button("🔀").clicked() {
                         self.req_sender
-                            .send(Request::ChangeRoot(PathBuf::from(
-                                self.root_music_path_input.clone(),
-                            )))
+                            .send(Request::ChangeRoots(self.music_roots_input.clone()))
                             .unwrap();
                         self.state = AppState::LoadingNewMusicDir;
+                        self.library_change = None;
+                    }
+
+                    let new_path = self.new_root_input.trim().to_string();
+                    let can_add = !new_path.is_empty()
+                        && !self.music_roots_input.iter().any(|r| r.path == new_path);
+                    if ui.add_enabled(can_add, Button::new("➕")).clicked() {
+                        self.music_roots_input.push(MusicRoot {
+                            path: new_path,
+                            enabled: true,
+                        });
+                        self.new_root_input.clear();
                     }
+
                     ui.add(
-                        TextEdit::singleline(&mut self.root_music_path_input)
+                        TextEdit::singleline(&mut self.new_root_input)
+                            .hint_text("Add a folder")
                             .desired_width(f32::INFINITY)
                             .font(TextStyle::Monospace),
                     );
                 },
             );
+
+            ui.collapsing("Why isn't a file played?", |ui| {
+                ui.horizontal(|ui| {
+                    let path = self.explain_input.trim().to_string();
+                    if ui
+                        .add_enabled(!path.is_empty(), Button::new("❓"))
+                        .clicked()
+                    {
+                        self.req_sender
+                            .send(Request::ExplainExclusion(PathBuf::from(path)))
+                            .unwrap();
+                    }
+                    ui.add(
+                        TextEdit::singleline(&mut self.explain_input)
+                            .hint_text("Path of a file or folder")
+                            .desired_width(f32::INFINITY)
+                            .font(TextStyle::Monospace),
+                    );
+                });
+                if let Some((path, explanation)) = &self.explanation {
+                    ui.label(RichText::new(path.display().to_string()).monospace());
+                    ui.label(explanation);
+                }
+            });
             ui.add_space(5.0);
         });
     }
@@ -0,0 +1,77 @@
+use crate::frontend::App;
+use crate::messages::Request;
+use eframe::egui::{
+    Align, Context, Id, Image, Label, Layout, RichText, ScrollArea, SidePanel, Vec2,
+};
+
+const COVER_SIZE: f32 = 40.0;
+
+impl App {
+    pub(crate) fn spawn_queue_side_panel(&mut self, ctx: &Context) {
+        SidePanel::right("queue")
+            .default_width(250.0)
+            .show(ctx, |ui| {
+                ui.add_space(5.0);
+                ui.heading("Up next");
+                ui.weak("Drag ☰ to reorder");
+                ui.separator();
+
+                let mut request = None;
+                ScrollArea::vertical().show(ui, |ui| {
+                    for (i, (path, metadata)) in self.queue.iter().enumerate() {
+                        let row = ui.horizontal(|ui| {
+                            // the handle and the track can be dragged onto another row
+                            ui.dnd_drag_source(Id::new(("queue", i)), i, |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("☰");
+                                    let texture = self
+                                        .queue_textures
+                                        .get(path)
+                                        .unwrap_or(&self.default_texture);
+                                    ui.add(
+                                        Image::new(texture)
+                                            .fit_to_exact_size(Vec2::splat(COVER_SIZE))
+                                            .rounding(3.0),
+                                    );
+                                    ui.vertical(|ui| {
+                                        ui.add(Label::new(&metadata.name).truncate());
+                                        ui.add(
+                                            Label::new(RichText::new(&metadata.artist).weak())
+                                                .truncate(),
+                                        );
+                                    });
+                                });
+                            });
+                            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                                if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                                    request = Some(Request::RemoveFromQueue(i, path.clone()));
+                                }
+                                if ui
+                                    .small_button("🎲")
+                                    .on_hover_text("Pick another track")
+                                    .clicked()
+                                {
+                                    request = Some(Request::RerollInQueue(i, path.clone()));
+                                }
+                            });
+                        });
+                        if let Some(from) = row.response.dnd_release_payload::<usize>() {
+                            if *from != i {
+                                request = Some(Request::MoveInQueue {
+                                    from: *from,
+                                    to: i,
+                                    path: self.queue[*from].0.clone(),
+                                });
+                            }
+                        }
+                    }
+                    if self.queue.is_empty() {
+                        ui.weak("Nothing queued");
+                    }
+                });
+                if let Some(request) = request {
+                    self.req_sender.send(request).unwrap();
+                }
+            });
+    }
+}
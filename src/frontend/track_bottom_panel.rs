@@ -3,7 +3,10 @@ use crate::frontend::eframe_app::{
 };
 use crate::frontend::App;
 use crate::messages::Request;
-use eframe::egui::{Align, Button, Color32, Context, Layout, RichText, Slider, TopBottomPanel, Ui};
+use crate::settings::{PlayOrder, Repeat};
+use eframe::egui::{
+    Align, Button, Color32, ComboBox, Context, Layout, RichText, Slider, TopBottomPanel, Ui,
+};
 use std::time::Duration;
 
 impl App {
@@ -22,6 +25,7 @@ impl App {
                     ui.horizontal_wrapped(|ui| {
                         ui.label(format!("{} - {}", &metadata.artist, &metadata.album));
                     });
+                    self.spawn_rating_stars(ui);
                 } else {
                     enable_duration_bar = false;
                 }
@@ -66,7 +70,12 @@ impl App {
                     });
                     cols[1].vertical_centered(|ui| self.spawn_pause_button(ui));
                     cols[2].with_layout(Layout::right_to_left(Align::TOP), |ui| {
+                        self.spawn_skip_album_button(ui);
                         self.spawn_skip_button(ui);
+                        self.spawn_previous_button(ui);
+                        ui.add_space(10.0);
+                        self.spawn_play_order_combo(ui);
+                        self.spawn_repeat_button(ui);
                     });
                 });
 
@@ -90,7 +99,7 @@ impl App {
             AppState::LoadingNewMusicDir => unreachable!(),
             AppState::Playing(pbs, _, _) => pbs == ProgressBarState::Active,
         };
-        enabled &= self.get_current_track_duration() != None;
+        enabled &= self.get_current_track_duration().is_some();
 
         let response = ui.add_enabled(
             enabled,
@@ -147,6 +156,83 @@ impl App {
         }
     }
 
+    pub fn spawn_rating_stars(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            for stars in 1..=5 {
+                let text = if self.current_rating.is_some_and(|r| r >= stars) {
+                    "★"
+                } else {
+                    "☆"
+                };
+                let response = ui.add(Button::new(RichText::new(text).size(16.0)).frame(false));
+                if response.clicked() {
+                    // clicking the current rating clears it
+                    let rating = (self.current_rating != Some(stars)).then_some(stars);
+                    self.current_rating = rating;
+                    self.req_sender.send(Request::SetRating(rating)).unwrap();
+                }
+            }
+        });
+    }
+
+    pub fn spawn_repeat_button(&mut self, ui: &mut Ui) {
+        let (text, hover) = match self.repeat_input {
+            Repeat::Off => (RichText::new("🔁").weak(), "Repeat: off"),
+            Repeat::All => (RichText::new("🔁"), "Repeat: folder, library or album"),
+            Repeat::One => (RichText::new("🔂"), "Repeat: current track"),
+        };
+        let response = ui
+            .add(Button::new(text.size(20.0)).rounding(7.0))
+            .on_hover_text(hover);
+        if response.clicked() {
+            self.repeat_input = self.repeat_input.get_next();
+            self.req_sender
+                .send(Request::SetRepeat(self.repeat_input))
+                .unwrap();
+        }
+    }
+
+    pub fn spawn_play_order_combo(&mut self, ui: &mut Ui) {
+        let before = self.play_order_input;
+        ComboBox::from_id_salt("play_order")
+            .selected_text(self.play_order_input.get_name())
+            .show_ui(ui, |ui| {
+                for order in PlayOrder::ALL {
+                    ui.selectable_value(&mut self.play_order_input, order, order.get_name());
+                }
+            });
+        if self.play_order_input != before {
+            self.req_sender
+                .send(Request::SetPlayOrder(self.play_order_input))
+                .unwrap();
+        }
+    }
+
+    pub fn spawn_previous_button(&mut self, ui: &mut Ui) {
+        let text = "⏮";
+        let response = ui.add_sized(
+            [40.0, 40.0],
+            Button::new(RichText::new(text).size(20.0)).rounding(7.0),
+        );
+        if response.clicked() {
+            self.req_sender.send(Request::Previous).unwrap();
+        }
+    }
+
+    pub fn spawn_skip_album_button(&mut self, ui: &mut Ui) {
+        let response = ui
+            .add_enabled(
+                self.play_order_input.plays_albums(),
+                Button::new(RichText::new("⏩").size(20.0))
+                    .min_size([40.0, 40.0].into())
+                    .rounding(7.0),
+            )
+            .on_hover_text("Skip to the next album");
+        if response.clicked() {
+            self.req_sender.send(Request::SkipAlbum).unwrap();
+        }
+    }
+
     pub fn spawn_skip_button(&mut self, ui: &mut Ui) {
         let text = "⏭";
         let response = ui.add_sized(
@@ -8,23 +8,36 @@ use eframe::egui::ViewportBuilder;
 
 use crate::messages::Event;
 
+mod audio_format;
 mod backend;
+mod cli_args;
+mod duplicate_report;
 mod frontend;
 mod image_utils;
 mod messages;
 mod music_dir_creation_error;
+mod scan_report;
 mod settings;
 mod track_metadata;
 
 pub const SETTINGS_RELATIVE_PATH: &str = "settings.json";
+pub const LIBRARY_INDEX_RELATIVE_PATH: &str = "library_index.json";
+pub const SHUFFLE_HISTORY_RELATIVE_PATH: &str = "shuffle_history.json";
+pub const SHUFFLE_BAG_RELATIVE_PATH: &str = "shuffle_bag.json";
+pub const TRACK_STATS_RELATIVE_PATH: &str = "track_stats.json";
+pub const SESSION_RELATIVE_PATH: &str = "session.json";
+pub const REPLAY_RELATIVE_PATH: &str = "replay.json";
+pub const REPLAY_PICKS_RELATIVE_PATH: &str = "replay_picks.jsonl";
 
 fn main() -> eframe::Result {
+    let args = cli_args::parse();
+
     // create channels
     let (req_sender, req_receiver) = unbounded::<messages::Request>();
     let (event_sender, event_receiver) = unbounded::<messages::Event>();
 
     // spawn backend thread
-    thread::spawn(move || backend::run(req_receiver, event_sender));
+    thread::spawn(move || backend::run(req_receiver, event_sender, args));
 
     // wait for initial settings message
     let settings = match event_receiver.recv() {
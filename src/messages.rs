@@ -2,29 +2,73 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::duplicate_report::DuplicateReport;
 use crate::music_dir_creation_error::MusicDirCreationError;
-use crate::settings::Settings;
+use crate::scan_report::ScanReport;
+use crate::settings::{MusicRoot, PlayOrder, Repeat, Settings};
 use crate::track_metadata::TrackMetaData;
 use eframe::egui::Context;
 
 #[derive(Clone)]
 pub enum Request {
-    ChangeRoot(PathBuf),
+    ChangeRoots(Vec<MusicRoot>),
     Play,
     Pause,
     JumpToFraction(f32), // [0, 1]
     Skip,
-    SetVolume(f32), // [0, 1]
+    Previous,              // restarts the current track if it played for a while
+    PlayNow(PathBuf),      // a track, or a folder in library order
+    PlayNext(PathBuf),     // right after the current track
+    Enqueue(Vec<PathBuf>), // after the other tracks asked for, before the random picks
+    SetRating(Option<u8>), // of the current track, 1 to 5 stars
+    SkipAlbum,             // the rest of the current album, for the orders playing whole albums
+    SetVolume(f32),        // [0, 1]
     ProvideContext(Context),
+    ExplainExclusion(PathBuf), // why a file is or isn't in the library
+    SetPlayOrder(PlayOrder),
+    SetRepeat(Repeat),
+    FindDuplicates,
+    SetPreferOneCopy(bool),
+    // upcoming tracks by index, the path guards against the queue having moved on
+    RemoveFromQueue(usize, PathBuf),
+    RerollInQueue(usize, PathBuf), // replaced by a new pick
+    MoveInQueue {
+        from: usize,
+        to: usize,
+        path: PathBuf,
+    },
 }
 
 #[derive(Debug)]
 pub enum Event {
     NewTrackPlaying(Option<Arc<TrackMetaData>>),
+    RestoringSession, // the last session plays again once its tracks are loaded
     NowPlaying,
     NowPaused,
-    ProgressUpdate(Duration), // [0, 1], always forward
-    JumpedTo(Duration),       // [0, 1]
+    CurrentRating(Option<u8>), // of the current track
+    QueueChanged(Vec<(PathBuf, Arc<TrackMetaData>)>), // the upcoming tracks
+    ProgressUpdate(Duration),  // [0, 1], always forward
+    JumpedTo(Duration),        // [0, 1]
     NewSettings(Settings),
     DirError(MusicDirCreationError),
+    RootErrors(Vec<(PathBuf, MusicDirCreationError)>), // folders that failed, the others may still play
+    LibraryChanged {
+        added: usize,
+        removed: usize,
+    },
+    ExclusionExplained(PathBuf, String),
+    ScanFinished {
+        tracks: usize,      // playable tracks in the library
+        report: ScanReport, // entries left out by the scan
+    },
+    DuplicatesProgress {
+        done: usize,
+        total: usize,
+    },
+    DuplicatesFound(DuplicateReport),
+    ScanProgress {
+        dirs: usize,
+        files: usize,
+        current_path: PathBuf,
+    },
 }
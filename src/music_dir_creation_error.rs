@@ -1,17 +1,41 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum MusicDirCreationError {
-    NotFound,
-    NotDir,
+    NotFound(PathBuf),
+    NotDir(PathBuf),
+    Unreadable(PathBuf, io::ErrorKind),
     Empty,
-    Unknown,
+    NoRootEnabled,
+    Cancelled,
+}
+
+impl MusicDirCreationError {
+    /// The error of reading the folder `path`.
+    pub fn from_io(path: PathBuf, e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Self::NotFound(path),
+            kind => Self::Unreadable(path, kind),
+        }
+    }
 }
 
 impl Display for MusicDirCreationError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::NotFound(path) => write!(f, "{} not found", path.display()),
+            Self::NotDir(path) => write!(f, "{} is not a folder", path.display()),
+            Self::Unreadable(path, kind) => write!(f, "{} can't be read: {kind}", path.display()),
+            Self::Empty => write!(
+                f,
+                "no playable audio files found inside the selected folders and their sub-folders"
+            ),
+            Self::NoRootEnabled => write!(f, "no music folder is enabled"),
+            Self::Cancelled => write!(f, "scan cancelled"),
+        }
     }
 }
 
@@ -0,0 +1,50 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// A file or folder the scan had to leave out. The rest of the scan goes on.
+#[derive(Debug, Clone)]
+pub struct ScanProblem {
+    pub path: PathBuf,
+    pub kind: ScanProblemKind,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ScanProblemKind {
+    Io(io::ErrorKind), // e.g. permission denied
+    BrokenLink,
+    Revisited, // already scanned through another path, e.g. a symlink loop
+    TooDeep,
+}
+
+impl Display for ScanProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let path = self.path.display();
+        match &self.kind {
+            ScanProblemKind::Io(kind) => write!(f, "{path}: {kind}"),
+            ScanProblemKind::BrokenLink => write!(f, "{path}: broken link"),
+            ScanProblemKind::Revisited => write!(f, "{path}: already scanned (symlink loop?)"),
+            ScanProblemKind::TooDeep => write!(f, "{path}: deeper than max_scan_depth"),
+        }
+    }
+}
+
+/// The problems met during a scan.
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    pub problems: Vec<ScanProblem>,
+}
+
+impl ScanReport {
+    pub fn add(&mut self, path: PathBuf, kind: ScanProblemKind) {
+        self.problems.push(ScanProblem { path, kind });
+    }
+
+    pub fn add_io_error(&mut self, path: PathBuf, e: &io::Error) {
+        self.add(path, ScanProblemKind::Io(e.kind()));
+    }
+
+    pub fn extend(&mut self, other: ScanReport) {
+        self.problems.extend(other.problems);
+    }
+}
@@ -1,15 +1,166 @@
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::{env, process};
 
 use serde::{Deserialize, Serialize};
 
+use crate::audio_format::AudioFormat;
 use crate::SETTINGS_RELATIVE_PATH;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)] // missing fields take their default value
 pub struct Settings {
-    pub root_music_path: String,
+    pub music_roots: Vec<MusicRoot>,
     pub volume: f32,
+    pub enabled_formats: Vec<AudioFormat>,
+    pub play_order: PlayOrder,
+    pub repeat: Repeat,
+    pub prefetch_secs: u32,      // playtime of the upcoming tracks kept loaded
+    pub max_prefetch_tracks: u8, // however short they are
+    pub crossfade_secs: f32,     // between tracks following each other, 0 to 12
+    pub skip_fade_secs: f32,     // when a track is skipped, 0 to 12
+    pub gapless_albums: bool,    // no crossfade inside an album played in order
+    pub shuffle_weighting: ShuffleWeighting,
+    pub no_repeat_window: NoRepeatWindow,
+    pub shuffle_spacing: ShuffleSpacing,
+    pub weight_curve: WeightCurve,
+    pub album_no_repeat_window: NoRepeatWindow, // in albums, for PlayOrder::RandomAlbum
+    pub shuffle_seed: Option<u64>, // the same random picks every session, none for a new seed each time
+    pub last_seed: Option<u64>,    // seed of the last session, reused by --replay
+    pub prefer_one_copy: bool,     // play only the best copy of each group of duplicates
+    // globs without '/' match file and folder names, the others match whole paths
+    pub exclude_globs: Vec<String>,
+    pub hidden_files: HiddenFiles,
+    pub min_track_duration_secs: u32, // 0 = no minimum
+    pub symlinks: SymlinkPolicy,
+    pub max_scan_depth: u32, // folders deeper than this below a root are not scanned
+    // single folder written by older versions, moved into music_roots when read
+    #[serde(skip_serializing)]
+    root_music_path: Option<String>,
+}
+
+/// Whether files and folders whose name starts with a dot are scanned.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum HiddenFiles {
+    #[default]
+    Skip,
+    Include,
+}
+
+/// Whether the scan goes through symbolic links. Each folder is scanned once at most,
+/// so links looping back to a parent folder are safe.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SymlinkPolicy {
+    #[default]
+    Follow,
+    Skip,
+}
+
+/// How many of the last picked tracks (or albums) the shuffle avoids.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum NoRepeatWindow {
+    Tracks(usize),
+    Percent(u8), // of the library
+}
+
+/// How many of the next random picks avoid the artist and the album folder of a track.
+/// 0 turns the spacing off.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub struct ShuffleSpacing {
+    pub artist: usize,
+    pub album: usize,
+}
+
+/// Shape of the weights of PlayOrder::Weighted. A track weighs
+/// `(stars / 3)^rating_exponent * (1 - e^(-days since played / recency_days)) * (1 - skip rate)^skip_exponent`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct WeightCurve {
+    pub rating_exponent: f32, // 0 ignores the ratings
+    pub recency_days: f32,    // 0 ignores when a track was played
+    pub skip_exponent: f32,   // 0 ignores the skips
+}
+
+/// A folder the library is made of. Disabled folders are kept but not played.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct MusicRoot {
+    pub path: String,
+    pub enabled: bool,
+}
+
+/// How the next track is chosen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum PlayOrder {
+    #[default]
+    Random, // independent picks, see ShuffleWeighting and NoRepeatWindow
+    ShuffleBag,       // every track once, in a random order, before any repeat
+    SequentialFolder, // the folder of the current track in track number order
+    SequentialTree,   // the whole library in order, folder after folder
+    RandomAlbum,      // a random folder in order, then another one
+    Weighted,         // by rating, time since last played and skip rate, see WeightCurve
+}
+
+impl PlayOrder {
+    pub const ALL: [PlayOrder; 6] = [
+        PlayOrder::Random,
+        PlayOrder::ShuffleBag,
+        PlayOrder::Weighted,
+        PlayOrder::RandomAlbum,
+        PlayOrder::SequentialFolder,
+        PlayOrder::SequentialTree,
+    ];
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            PlayOrder::Random => "Shuffle",
+            PlayOrder::ShuffleBag => "Shuffle bag",
+            PlayOrder::SequentialFolder => "Folder in order",
+            PlayOrder::SequentialTree => "Library in order",
+            PlayOrder::RandomAlbum => "Random album",
+            PlayOrder::Weighted => "Weighted shuffle",
+        }
+    }
+
+    /// Whether the tracks of a folder play in their order, one after another.
+    pub fn plays_in_order(self) -> bool {
+        matches!(
+            self,
+            PlayOrder::SequentialFolder | PlayOrder::SequentialTree | PlayOrder::RandomAlbum
+        )
+    }
+
+    /// Whether the tracks come album after album, so that one can be skipped as a whole.
+    pub fn plays_albums(self) -> bool {
+        matches!(self, PlayOrder::RandomAlbum | PlayOrder::SequentialTree)
+    }
+}
+
+/// What plays again once it ended. The shuffle orders never end, so `All` changes nothing for them.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Repeat {
+    #[default]
+    Off,
+    One, // the current track
+    All, // the folder or library played in order, or the current random album
+}
+
+impl Repeat {
+    pub fn get_next(self) -> Self {
+        match self {
+            Repeat::Off => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+        }
+    }
+}
+
+/// How the random picks are spread over the folder tree.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ShuffleWeighting {
+    #[default]
+    Track, // every track is equally likely
+    AlbumFolder,    // every folder holding tracks is equally likely, whatever its size
+    TopLevelFolder, // every folder directly inside the root is equally likely
 }
 
 impl Default for Settings {
@@ -19,43 +170,100 @@ impl Default for Settings {
             Err(_) => String::new(),
         };
         Self {
-            root_music_path: dir,
+            music_roots: vec![MusicRoot {
+                path: dir,
+                enabled: true,
+            }],
             volume: 0.5,
+            enabled_formats: AudioFormat::ALL.to_vec(),
+            play_order: PlayOrder::default(),
+            repeat: Repeat::default(),
+            prefetch_secs: 300,
+            max_prefetch_tracks: 10,
+            crossfade_secs: 0.0,
+            skip_fade_secs: 0.5,
+            gapless_albums: true,
+            shuffle_weighting: ShuffleWeighting::default(),
+            no_repeat_window: NoRepeatWindow::Percent(25),
+            weight_curve: WeightCurve {
+                rating_exponent: 2.0,
+                recency_days: 7.0,
+                skip_exponent: 1.0,
+            },
+            shuffle_spacing: ShuffleSpacing {
+                artist: 3,
+                album: 5,
+            },
+            album_no_repeat_window: NoRepeatWindow::Percent(25),
+            shuffle_seed: None,
+            last_seed: None,
+            prefer_one_copy: false,
+            // NAS metadata and recycle bin folders
+            exclude_globs: vec!["@eaDir".to_string(), "#recycle".to_string()],
+            hidden_files: HiddenFiles::default(),
+            min_track_duration_secs: 0,
+            symlinks: SymlinkPolicy::default(),
+            max_scan_depth: 32,
+            root_music_path: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn get_enabled_roots(&self) -> Vec<PathBuf> {
+        self.music_roots
+            .iter()
+            .filter(|r| r.enabled)
+            .map(|r| PathBuf::from(&r.path))
+            .collect()
+    }
+
+    fn migrate(&mut self) {
+        if let Some(path) = self.root_music_path.take() {
+            self.music_roots = vec![MusicRoot {
+                path,
+                enabled: true,
+            }];
         }
     }
 }
 
-pub fn read() -> Settings {
-    match File::open(SETTINGS_RELATIVE_PATH) {
-        Ok(settings_file) => serde_json::from_reader::<&File, Settings>(&settings_file).unwrap_or_else(|e| {
-            eprintln!("Error in parsing {SETTINGS_RELATIVE_PATH}: {e}");
+pub fn read(dir: &Path) -> Settings {
+    let path = dir.join(SETTINGS_RELATIVE_PATH);
+    match File::open(&path) {
+        Ok(settings_file) => serde_json::from_reader::<&File, Settings>(&settings_file).map(|mut s| {
+            s.migrate();
+            s
+        }).unwrap_or_else(|e| {
+            eprintln!("Error in parsing {}: {e}", path.display());
             eprintln!("Probably due to corrupted or malformed settings file. Settings will be restored to default values.");
             let new_settings = Settings::default();
-            write(&new_settings);
+            write(dir, &new_settings);
             new_settings
         }),
         Err(e) => {
-            eprintln!("Error in reading {SETTINGS_RELATIVE_PATH}: {e}");
+            eprintln!("Error in reading {}: {e}", path.display());
             let new_settings = Settings::default();
-            write(&new_settings);
+            write(dir, &new_settings);
             new_settings
         }
     }
 }
 
-pub fn write(data: &Settings) {
+pub fn write(dir: &Path, data: &Settings) {
+    let path = dir.join(SETTINGS_RELATIVE_PATH);
     let json_string = serde_json::to_string(data).unwrap_or_else(|e| {
         eprintln!("Failed to serialize settings: {e}");
         process::exit(1);
     });
 
-    let mut file = File::create(SETTINGS_RELATIVE_PATH).unwrap_or_else(|e| {
-        eprintln!("Failed to create file '{}': {e}", SETTINGS_RELATIVE_PATH);
+    let mut file = File::create(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to create file '{}': {e}", path.display());
         process::exit(1);
     });
 
     file.write_all(json_string.as_ref()).unwrap_or_else(|e| {
-        eprintln!("Failed to write to file '{}': {e}", SETTINGS_RELATIVE_PATH);
+        eprintln!("Failed to write to file '{}': {e}", path.display());
         process::exit(1);
     });
 }